@@ -1,3 +1,4 @@
+#[allow(clippy::enum_variant_names)]
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("Generic {0}")]
@@ -20,4 +21,21 @@ pub enum Error {
 
     #[error(transparent)]
     ParseError(#[from] std::num::ParseIntError),
+
+    #[error("Linha {line}, coluna {column}: {reason}")]
+    Parse {
+        line: usize,
+        column: usize,
+        reason: String,
+    },
+}
+
+impl Error {
+    pub fn parse(line: usize, column: usize, reason: impl Into<String>) -> Error {
+        Error::Parse {
+            line,
+            column,
+            reason: reason.into(),
+        }
+    }
 }
@@ -6,11 +6,17 @@ mod utils;
 extern crate log;
 use clap::{Parser, Subcommand};
 use prelude::*;
-use std::{fs::File, path::PathBuf};
+use std::{
+    collections::BTreeSet,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
 use utils::{
     command::Command,
-    configuration::{Config, ConfigField},
-    olt::Interface,
+    configuration::{Config, ConfigField, ConfigInfo, ConfigSource, ScriptFormat},
+    olt::{Interface, Olt, OltModel, PortMap, VlanMap},
+    session::{OltSession, SshTarget},
 };
 
 #[derive(Parser, Debug, PartialEq)]
@@ -19,46 +25,208 @@ struct Args {
     /// Opções para a criação de script
     #[command(subcommand)]
     command: Commands,
-    /// Arquivo final para guardar o script
+    /// Arquivo final para guardar o script (no `apply` e `provision`, o registro em JSON,
+    /// e no `discover`, o .csv das ONU encontradas)
     #[arg(short, long, value_name = "FILE")]
     output: PathBuf,
+    /// Formato do script: `backup`, com os campos `!<...>`, ou `cli`, pronto para
+    /// colar no terminal da OLT
+    #[arg(short, long, value_enum, default_value_t)]
+    format: ScriptFormat,
 }
 
 #[derive(Subcommand, Debug, PartialEq)]
 enum Commands {
     /// Migrar script da linha antiga para a nova
     Migrate {
-        /// Arquivo de configuração antigo
-        #[arg(short, long, value_name = "ARQUIVO")]
-        old: PathBuf,
+        /// Arquivo de configuração antigo ou OLT de origem
+        #[arg(short, long, value_name = "ARQUIVO|ssh://usuario@host")]
+        old: ConfigSource,
         /// Arquivo de configuração base
         #[arg(short, long, value_name = "ARQUIVO")]
         base: PathBuf,
+        /// Modelo da OLT de origem (c300, c320, c600, c650...)
+        #[arg(long, value_name = "MODELO")]
+        from_model: Option<OltModel>,
+        /// Modelo da OLT de destino, que define a sintaxe do script
+        #[arg(long, value_name = "MODELO", default_value = "c600")]
+        to_model: OltModel,
+        /// Arquivo .toml com a porta nova de cada porta PON antiga
+        #[arg(long, value_name = "ARQUIVO.toml")]
+        map: Option<PathBuf>,
+        /// Arquivo .toml com a VLAN nova de cada VLAN antiga, geral ou por porta PON
+        #[arg(long, value_name = "ARQUIVO.toml")]
+        vlan_map: Option<PathBuf>,
     },
     /// Criar script de ONU a partir de arquivos com as informações
     Create {
-        /// Arquivo .csv com as informações das ONU: SN, perfil e usuário e senha PPPoE
+        /// Arquivo .csv com as informações das ONU: SN, modelo e modo (pppoe, dhcp,
+        /// bridge ou static) com os dados do modo, e opcionalmente nome, descrição e
+        /// perfis de upload e download. Uma linha por serviço: linhas com o mesmo SN
+        /// formam uma única ONU, cada uma com a sua VLAN (coluna `vlan`)
         #[arg(short, long, value_name = "ARQUIVO.csv")]
         onu_param: PathBuf,
 
-        /// Vlan que será utilizada para configurar as ONU
+        /// Vlan que será utilizada nas linhas sem a coluna `vlan`
         #[arg(short, long, value_name = "VLAN_ID")]
         vlan: u16,
 
-        /// Interface PON em que as ONU estão situadas
-        #[arg(short, long, value_name = "gpon_olt-1/x/y")]
-        interface: Interface,
+        /// Interface PON das linhas sem a coluna `interface`
+        #[arg(short, long, value_name = "gpon_olt-1/x/y[:id]")]
+        interface: Option<Interface>,
+
+        /// Configuração atual da OLT, para não repetir IDs de ONU já existentes
+        #[arg(short, long, value_name = "ARQUIVO|ssh://usuario@host")]
+        existing: Option<ConfigSource>,
+
+        /// Modelo da OLT, que define a sintaxe do script
+        #[arg(long, value_name = "MODELO", default_value = "c600")]
+        model: OltModel,
+    },
+
+    /// Enviar um script para a OLT via SSH
+    Apply {
+        /// Script gerado pelo `migrate` ou `create`
+        #[arg(short, long, value_name = "ARQUIVO")]
+        script: PathBuf,
+
+        /// OLT de destino
+        #[arg(short, long, value_name = "ssh://usuario@host[:porta]")]
+        target: SshTarget,
+
+        /// Apenas mostra os comandos que seriam enviados
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Não pede confirmação antes de enviar
+        #[arg(short, long)]
+        yes: bool,
+    },
+
+    /// Provisionar as ONU do .csv que estiverem aguardando configuração na OLT
+    Provision {
+        /// Arquivo .csv no mesmo formato do `create`
+        #[arg(short, long, value_name = "ARQUIVO.csv")]
+        onu_param: PathBuf,
+
+        /// Vlan que será utilizada nas linhas sem a coluna `vlan`
+        #[arg(short, long, value_name = "VLAN_ID")]
+        vlan: u16,
+
+        /// OLT de destino
+        #[arg(short, long, value_name = "ssh://usuario@host[:porta]")]
+        target: SshTarget,
+
+        /// Modelo da OLT de destino
+        #[arg(long, value_name = "MODELO", default_value = "c600")]
+        model: OltModel,
+
+        /// Apenas mostra os comandos que seriam enviados
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Não pede confirmação antes de enviar
+        #[arg(short, long)]
+        yes: bool,
+    },
+
+    /// Procurar ONU não configuradas na OLT e gerar o .csv para o `create`
+    Discover {
+        /// OLT a consultar
+        #[arg(short, long, value_name = "ssh://usuario@host[:porta]")]
+        target: SshTarget,
+
+        /// Modelo da OLT consultada
+        #[arg(long, value_name = "MODELO", default_value = "c600")]
+        model: OltModel,
     },
 
     Show {
-        /// Arquivo para mostrar
-        #[arg(long, value_name = "ARQUIVO")]
-        from: PathBuf,
+        /// Arquivo ou OLT para mostrar
+        #[arg(long, value_name = "ARQUIVO|ssh://usuario@host")]
+        from: ConfigSource,
         #[arg(long, value_name = "CAMPO")]
         field: Option<ConfigField>,
+
+        /// Mostra um resumo da OLT: ONU e IDs livres por porta, VLAN e perfis em uso
+        #[arg(long)]
+        summary: bool,
+
+        /// Modelo da OLT, usado no resumo
+        #[arg(long, value_name = "MODELO", default_value = "c600")]
+        model: OltModel,
     },
 }
 
+// Mostra os comandos que seriam enviados à OLT, com o aninhamento
+fn print_session(config: &Config) {
+    for (depth, command) in config.session_commands() {
+        println!("{}{command}", "  ".repeat(depth));
+    }
+}
+
+// Mostra as ONU e IDs livres de cada porta PON, as VLAN e os perfis em uso
+fn print_summary(olt: &Olt) {
+    println!("{}: {} ONU", olt.model(), olt.onus().len());
+
+    let per_port = olt.onus_per_port();
+    for interface in olt.interfaces() {
+        let card = olt
+            .card(interface.slot)
+            .map_or(String::new(), |c| format!(" ({} {})", c.name, c.kind));
+        let count = per_port
+            .get(&(interface.slot, interface.port))
+            .unwrap_or(&0);
+        println!(
+            "{}{card}: {count} ONU, {} IDs livres",
+            olt.dialect().gpon_olt(interface),
+            olt.free_ids(interface).len()
+        );
+    }
+
+    let vlans: Vec<String> = olt.used_vlans().iter().map(u16::to_string).collect();
+    if !vlans.is_empty() {
+        println!("VLAN em uso: {}", vlans.join(", "));
+    }
+    for (kind, names) in olt.profiles() {
+        let names: Vec<String> = names.into_iter().collect();
+        println!("Perfis {kind}: {}", names.join(", "));
+    }
+}
+
+// Pergunta ao usuário se o script deve ser enviado
+fn confirm(config: &Config, target: &SshTarget) -> Result<bool> {
+    let commands = config.session_commands();
+    print!("Enviar {} comandos para {target}? [s/N] ", commands.len());
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+
+    let accepted = matches!(answer.trim(), "s" | "S" | "sim");
+    if !accepted {
+        println!("Operação cancelada.");
+    }
+
+    Ok(accepted)
+}
+
+// Envia o script e guarda o registro de cada comando com a resposta da OLT
+fn send(session: &mut OltSession, config: &Config, log: &Path) -> Result<()> {
+    let mut results = Vec::new();
+    let applied = session.apply(config, &mut results);
+
+    let rejected = results.iter().filter(|r| !r.accepted).count();
+    println!(
+        "{} comandos enviados, {rejected} rejeitados.",
+        results.len()
+    );
+
+    // O log é gravado mesmo que a sessão caia no meio do envio
+    let log_file = File::create(log)?;
+    serde_json::to_writer_pretty(log_file, &results)?;
+    applied
+}
+
 fn main() -> Result<()> {
     // Inicia o serviço de log
     env_logger::init();
@@ -71,38 +239,166 @@ fn main() -> Result<()> {
 
     // Verifica o comando utilizado
     match cli_args.command {
-        Commands::Migrate { old, base } => {
+        Commands::Migrate {
+            old,
+            base,
+            from_model,
+            to_model,
+            map,
+            vlan_map,
+        } => {
             // Cria um objeto de configuração a partir de um backup de uma OLT.
-            let startrun = File::open(old)?;
             let base_file = File::open(base)?;
-            let config = Config::from(base_file);
-            script += config;
+            let mut olt = Olt::new(to_model, Config::try_from(base_file)?);
 
-            let config = Config::from(startrun);
+            let source = Olt::load(from_model.unwrap_or(to_model), &old)?;
+            if let Some(model) = from_model {
+                let foreign = source.configuration().foreign_interfaces(model.dialect());
+                if foreign > 0 {
+                    warn!("{foreign} interfaces da origem não estão na sintaxe da {model}");
+                }
+            }
 
-            let onu_script = config.extract_onu();
-            for onu in onu_script {
-                let cmd = onu.configure_script();
-                script += cmd;
+            // Nenhum script é gerado se alguma ONU não couber no chassi de destino
+            let mut onus = source.into_onus();
+            // As VLAN são trocadas de acordo com a porta de origem
+            if let Some(path) = vlan_map {
+                let summary = VlanMap::from_file(&path)?.apply(&mut onus);
+                for ((old, new), count) in summary.iter() {
+                    println!("VLAN {old} -> {new}: {count} ONU");
+                }
             }
+            if let Some(path) = map {
+                PortMap::from_file(&path)?.apply(&mut onus)?;
+            }
+            olt.validate_onus(&onus)?;
+            for onu in onus {
+                olt.add_onu(onu);
+            }
+            script += olt.into_configuration();
+            script.sort(to_model.dialect());
         }
         Commands::Create {
             onu_param,
             vlan,
             interface,
+            existing,
+            model,
         } => {
             // Carrega o arquivo de configuração das ONU
             let equipment_info = File::open(onu_param)?;
 
+            // OLT com as ONU já provisionadas
+            let mut olt = match existing {
+                Some(source) => Olt::load(model, &source)?,
+                None => Olt::from(model),
+            };
+
             // Adiciona as configurações das ONU no script
-            let new_script = Command::onu_script_from_file(equipment_info, vlan, interface)?;
+            let new_script =
+                Command::onu_script_from_file(equipment_info, vlan, interface, &mut olt)?;
             script += new_script;
         }
-        Commands::Show { from, field } => {
-            let file = File::open(from)?;
-            let config = Config::from(file);
+        Commands::Apply {
+            script,
+            target,
+            dry_run,
+            yes,
+        } => {
+            // O script vai direto para a OLT, então não aceita linhas suspeitas
+            let config = Config::parse_strict(&fs::read_to_string(script)?)?;
+            if dry_run {
+                print_session(&config);
+                return Ok(());
+            }
+
+            if !yes && !confirm(&config, &target)? {
+                return Ok(());
+            }
+
+            let mut session = OltSession::open(&target)?;
+            send(&mut session, &config, &cli_args.output)?;
+            session.close()?;
+            return Ok(());
+        }
+        Commands::Provision {
+            onu_param,
+            vlan,
+            target,
+            model,
+            dry_run,
+            yes,
+        } => {
+            let mut rows = ConfigInfo::from_file(File::open(onu_param)?)?;
+
+            // Descobre em qual porta cada ONU está e quais IDs já estão em uso
+            let mut session = OltSession::open(&target)?;
+            let uncfg = session.uncfg_onus()?;
+            let mut olt = Olt::from_session(model, &mut session)?;
+
+            // Um SN com vários serviços aparece em várias linhas
+            let mut pending = BTreeSet::new();
+            rows.retain_mut(|row| {
+                match uncfg.iter().find(|u| u.sn.eq_ignore_ascii_case(&row.sn)) {
+                    Some(found) => {
+                        row.interface = ConfigInfo::from(found).interface;
+                        true
+                    }
+                    None => {
+                        pending.insert(row.sn.clone());
+                        false
+                    }
+                }
+            });
+
+            for sn in pending.iter() {
+                println!("Pendente: {sn} não aparece entre as ONU não configuradas.");
+            }
+
+            let config = olt.provision(rows, vlan, None)?;
+
+            if config.0.is_empty() {
+                println!("Nenhuma ONU para provisionar.");
+            } else if dry_run {
+                print_session(&config);
+            } else if yes || confirm(&config, &target)? {
+                send(&mut session, &config, &cli_args.output)?;
+            }
+
+            session.close()?;
+            return Ok(());
+        }
+        Commands::Discover { target, model } => {
+            let mut session = OltSession::open(&target)?;
+            let uncfg = session.uncfg_onus()?;
+            session.close()?;
+
+            for onu in uncfg.iter() {
+                println!(
+                    "{}\t{}\t{}",
+                    model.dialect().gpon_olt(&onu.interface),
+                    onu.sn,
+                    onu.type_guess().unwrap_or_default()
+                );
+            }
+            println!("{} ONU não configuradas.", uncfg.len());
+
+            // Gera o .csv faltando só os dados de acesso de cada cliente.
+            let infos: Vec<ConfigInfo> = uncfg.iter().map(ConfigInfo::from).collect();
+            ConfigInfo::to_file(&infos, File::create(cli_args.output)?)?;
+            return Ok(());
+        }
+        Commands::Show {
+            from,
+            field,
+            summary,
+            model,
+        } => {
+            let config = Config::load(&from)?;
 
-            if let Some(f) = field {
+            if summary {
+                print_summary(&Olt::new(model, config));
+            } else if let Some(f) = field {
                 if let Some(c) = config.0.get(&f) {
                     println!("===============");
                     for command in c {
@@ -128,6 +424,6 @@ fn main() -> Result<()> {
     // Gera um arquivo para colocar o script.
     let script_file = File::create(cli_args.output)?;
     // Escreve o script no arquivo.
-    script.to_file(script_file)?;
+    script.to_file(script_file, cli_args.format)?;
     Ok(())
 }
@@ -1,5 +1,6 @@
 use crate::utils::command::{CmdArg0, CmdArg1, CmdArg2, CmdArg3, CmdArg4, Command, CommandBuilder};
 
+use crate::utils::dialect::Dialect;
 use crate::utils::olt::Interface;
 
 use core::marker::PhantomData;
@@ -12,30 +13,29 @@ pub struct InterfaceOnu;
 pub struct InterfaceVport;
 
 impl CommandBuilder<Interface, CmdArg0> {
-    pub fn gpon_olt(self, interface: &Interface) -> CommandBuilder<InterfaceOlt, CmdArg0> {
-        let interface = format!(
-            "{} gpon_olt-1/{}/{}",
-            self.command, interface.slot, interface.port
-        );
+    pub fn gpon_olt_in(
+        self,
+        dialect: &dyn Dialect,
+        interface: &Interface,
+    ) -> CommandBuilder<InterfaceOlt, CmdArg0> {
+        let interface = format!("{} {}", self.command, dialect.gpon_olt(interface));
 
         CommandBuilder {
-            command: interface.into(),
+            command: Command::Raw(interface.into()),
             command_level: PhantomData,
             arg: PhantomData,
         }
     }
 
-    pub fn gpon_onu(self, interface: &Interface) -> CommandBuilder<InterfaceOnu, CmdArg0> {
-        let interface = format!(
-            "{} gpon_onu-1/{}/{}:{}",
-            self.command,
-            interface.slot,
-            interface.port,
-            interface.id.unwrap()
-        );
+    pub fn gpon_onu_in(
+        self,
+        dialect: &dyn Dialect,
+        interface: &Interface,
+    ) -> CommandBuilder<InterfaceOnu, CmdArg0> {
+        let interface = format!("{} {}", self.command, dialect.gpon_onu(interface));
 
         CommandBuilder {
-            command: interface.into(),
+            command: Command::Raw(interface.into()),
             command_level: PhantomData,
             arg: PhantomData,
         }
@@ -47,14 +47,16 @@ impl CommandBuilder<Interface, CmdArg0> {
         service: u8,
     ) -> CommandBuilder<InterfaceVport, CmdArg0> {
         CommandBuilder {
-            command: format!(
-                "{} vport-1/{}/{}.{}:{service}",
-                self.command,
-                interface.slot,
-                interface.port,
-                interface.id.unwrap()
-            )
-            .into(),
+            command: Command::Raw(
+                format!(
+                    "{} vport-1/{}/{}.{}:{service}",
+                    self.command,
+                    interface.slot,
+                    interface.port,
+                    interface.id.unwrap()
+                )
+                .into(),
+            ),
             command_level: PhantomData,
             arg: PhantomData,
         }
@@ -62,9 +64,15 @@ impl CommandBuilder<Interface, CmdArg0> {
 }
 
 impl CommandBuilder<InterfaceOlt, CmdArg0> {
+    // O tipo é definido em seguida, pelo `type`
     pub fn onu(self, id: u8) -> CommandBuilder<InterfaceOlt, CmdArg1> {
         CommandBuilder {
-            command: format!("onu {id}").into(),
+            command: Command::OnuAdd {
+                id,
+                onu_type: "".into(),
+                sn: None,
+                pw: None,
+            },
             command_level: PhantomData,
             arg: PhantomData,
         }
@@ -72,10 +80,12 @@ impl CommandBuilder<InterfaceOlt, CmdArg0> {
 }
 
 impl CommandBuilder<InterfaceOlt, CmdArg1> {
-    pub fn r#type(self, onu_type: impl Into<String>) -> CommandBuilder<InterfaceOlt, CmdArg2> {
-        let command = format!("{} type {}", self.command, onu_type.into());
+    pub fn r#type(mut self, value: impl Into<String>) -> CommandBuilder<InterfaceOlt, CmdArg2> {
+        if let Command::OnuAdd { onu_type, .. } = &mut self.command {
+            *onu_type = value.into().into();
+        }
         CommandBuilder {
-            command: command.into(),
+            command: self.command,
             command_level: PhantomData,
             arg: PhantomData,
         }
@@ -83,15 +93,27 @@ impl CommandBuilder<InterfaceOlt, CmdArg1> {
 }
 
 impl CommandBuilder<InterfaceOlt, CmdArg2> {
-    pub fn sn(self, sn: impl Into<String>) -> CommandBuilder<InterfaceOlt, CmdArg3> {
+    pub fn sn(mut self, value: impl Into<String>) -> CommandBuilder<InterfaceOlt, CmdArg3> {
+        if let Command::OnuAdd { sn, .. } = &mut self.command {
+            *sn = Some(value.into().into());
+        }
         CommandBuilder {
-            command: format!("{} sn {}", self.command, sn.into()).into(),
+            command: self.command,
             command_level: PhantomData,
             arg: PhantomData,
         }
     }
 
-    //TODO: missing fields
+    pub fn pw(mut self, password: impl Into<String>) -> CommandBuilder<InterfaceOlt, CmdArg3> {
+        if let Command::OnuAdd { pw, .. } = &mut self.command {
+            *pw = Some(password.into().into());
+        }
+        CommandBuilder {
+            command: self.command,
+            command_level: PhantomData,
+            arg: PhantomData,
+        }
+    }
 }
 
 impl CommandBuilder<InterfaceOlt, CmdArg3> {
@@ -99,31 +121,104 @@ impl CommandBuilder<InterfaceOlt, CmdArg3> {
         self.command
     }
 
+    pub fn pw(mut self, password: impl Into<String>) -> CommandBuilder<InterfaceOlt, CmdArg3> {
+        if let Command::OnuAdd { pw, .. } = &mut self.command {
+            *pw = Some(password.into().into());
+        }
+        CommandBuilder {
+            command: self.command,
+            command_level: PhantomData,
+            arg: PhantomData,
+        }
+    }
+
+    #[allow(dead_code)]
     pub fn vport_mode(self) -> CommandBuilder<InterfaceOlt, CmdArg4> {
         CommandBuilder {
-            command: format!("{} vport_mode", self.command).into(),
+            command: Command::Raw(format!("{} vport_mode", self.command).into()),
             command_level: PhantomData,
             arg: PhantomData,
         }
     }
 }
 
+#[allow(dead_code)]
 impl CommandBuilder<InterfaceOlt, CmdArg4> {
     pub fn gemport(self) -> Command {
-        let command = format!("{} gemport", self.command);
-        self.command
+        Command::Raw(format!("{} gemport", self.command).into())
     }
 
     pub fn manual(self) -> Command {
-        let command = format!("{} manual", self.command);
-        self.command
+        Command::Raw(format!("{} manual", self.command).into())
     }
 }
 
 impl CommandBuilder<InterfaceOnu, CmdArg0> {
+    // service-port no estilo C300
+    pub fn service_port(self, number: u8) -> CommandBuilder<InterfaceVport, CmdArg1> {
+        CommandBuilder {
+            command: Command::ServicePort {
+                id: number,
+                vport: None,
+                user_vlan: 0,
+                user_etype: None,
+                vlan: None,
+                svlan: None,
+                ingress: None,
+                egress: None,
+                extra: None,
+            },
+            command_level: PhantomData,
+            arg: PhantomData,
+        }
+    }
+
+    pub fn name(self, name: impl Into<String>) -> Command {
+        Command::Name(name.into().into())
+    }
+
+    pub fn description(self, description: impl Into<String>) -> Command {
+        Command::Description(description.into().into())
+    }
+
+    pub fn sn_bind(self, mode: impl Into<String>) -> Command {
+        Command::SnBind(mode.into().into())
+    }
+
+    pub fn vport_mode(self, mode: impl Into<String>) -> Command {
+        Command::VportMode(mode.into().into())
+    }
+
+    pub fn vport(self, number: u8) -> CommandBuilder<InterfaceOnu, CmdArg1> {
+        CommandBuilder {
+            command: Command::Vport {
+                id: number,
+                map_type: "".into(),
+            },
+            command_level: PhantomData,
+            arg: PhantomData,
+        }
+    }
+
+    pub fn vport_map(self, vport: u8, gemport: u8) -> CommandBuilder<InterfaceOnu, CmdArg1> {
+        CommandBuilder {
+            command: Command::VportMap {
+                vport,
+                gemport,
+                vlan: 0,
+            },
+            command_level: PhantomData,
+            arg: PhantomData,
+        }
+    }
+
     pub fn tcont(self, number: u8) -> CommandBuilder<InterfaceOnu, CmdArg1> {
         CommandBuilder {
-            command: format!("tcont {number} ").into(),
+            command: Command::Tcont {
+                id: number,
+                name: None,
+                profile: "".into(),
+            },
             command_level: PhantomData,
             arg: PhantomData,
         }
@@ -131,7 +226,11 @@ impl CommandBuilder<InterfaceOnu, CmdArg0> {
 
     pub fn gemport(self, number: u8) -> CommandBuilder<InterfaceOnu, CmdArg1> {
         CommandBuilder {
-            command: format!("gemport {number}").into(),
+            command: Command::Gemport {
+                id: number,
+                name: None,
+                tcont: 0,
+            },
             command_level: PhantomData,
             arg: PhantomData,
         }
@@ -139,13 +238,43 @@ impl CommandBuilder<InterfaceOnu, CmdArg0> {
 }
 
 impl CommandBuilder<InterfaceOnu, CmdArg1> {
-    pub fn profile(self, prof: impl Into<String>) -> Command {
-        Command(format!("{} profile {}", self.command, prof.into()).into())
+    // tcont e gemport
+    pub fn name(mut self, value: impl Into<String>) -> CommandBuilder<InterfaceOnu, CmdArg1> {
+        if let Command::Tcont { name, .. } | Command::Gemport { name, .. } = &mut self.command {
+            *name = Some(value.into().into());
+        }
+        self
+    }
+
+    // vport
+    pub fn map_type(mut self, value: impl Into<String>) -> Command {
+        if let Command::Vport { map_type, .. } = &mut self.command {
+            *map_type = value.into().into();
+        }
+        self.command
+    }
+
+    // vport-map
+    pub fn vlan(mut self, value: u16) -> Command {
+        if let Command::VportMap { vlan, .. } = &mut self.command {
+            *vlan = value;
+        }
+        self.command
+    }
+
+    pub fn profile(mut self, prof: impl Into<String>) -> Command {
+        if let Command::Tcont { profile, .. } = &mut self.command {
+            *profile = prof.into().into();
+        }
+        self.command
     }
 
-    pub fn tcont(self, number: u8) -> CommandBuilder<InterfaceOnu, CmdArg2> {
+    pub fn tcont(mut self, number: u8) -> CommandBuilder<InterfaceOnu, CmdArg2> {
+        if let Command::Gemport { tcont, .. } = &mut self.command {
+            *tcont = number;
+        }
         CommandBuilder {
-            command: format!("{} tcont {number}", self.command).into(),
+            command: self.command,
             command_level: PhantomData,
             arg: PhantomData,
         }
@@ -161,7 +290,17 @@ impl CommandBuilder<InterfaceOnu, CmdArg2> {
 impl CommandBuilder<InterfaceVport, CmdArg0> {
     pub fn service_port(self, number: u8) -> CommandBuilder<InterfaceVport, CmdArg1> {
         CommandBuilder {
-            command: format!("service-port {number} ").into(),
+            command: Command::ServicePort {
+                id: number,
+                vport: None,
+                user_vlan: 0,
+                user_etype: None,
+                vlan: None,
+                svlan: None,
+                ingress: None,
+                egress: None,
+                extra: None,
+            },
             command_level: PhantomData,
             arg: PhantomData,
         }
@@ -169,9 +308,20 @@ impl CommandBuilder<InterfaceVport, CmdArg0> {
 }
 
 impl CommandBuilder<InterfaceVport, CmdArg1> {
-    pub fn user_vlan(self, vlan: u16) -> CommandBuilder<InterfaceVport, CmdArg2> {
+    // Estilo C300, com o service-port dentro da interface gpon_onu
+    pub fn vport(mut self, number: u8) -> CommandBuilder<InterfaceVport, CmdArg1> {
+        if let Command::ServicePort { vport, .. } = &mut self.command {
+            *vport = Some(number);
+        }
+        self
+    }
+
+    pub fn user_vlan(mut self, vlan: u16) -> CommandBuilder<InterfaceVport, CmdArg2> {
+        if let Command::ServicePort { user_vlan, .. } = &mut self.command {
+            *user_vlan = vlan;
+        }
         CommandBuilder {
-            command: format!("{} user-vlan {vlan}", self.command).into(),
+            command: self.command,
             command_level: PhantomData,
             arg: PhantomData,
         }
@@ -179,9 +329,23 @@ impl CommandBuilder<InterfaceVport, CmdArg1> {
 }
 
 impl CommandBuilder<InterfaceVport, CmdArg2> {
-    pub fn vlan(self, vlan: u16) -> CommandBuilder<InterfaceVport, CmdArg3> {
+    // Tipo de quadro aceito na VLAN de usuário, como `PPPOE`
+    pub fn user_etype(
+        mut self,
+        etype: impl Into<String>,
+    ) -> CommandBuilder<InterfaceVport, CmdArg2> {
+        if let Command::ServicePort { user_etype, .. } = &mut self.command {
+            *user_etype = Some(etype.into().into());
+        }
+        self
+    }
+
+    pub fn vlan(mut self, value: u16) -> CommandBuilder<InterfaceVport, CmdArg3> {
+        if let Command::ServicePort { vlan, .. } = &mut self.command {
+            *vlan = Some(value);
+        }
         CommandBuilder {
-            command: format!("{} vlan {vlan}", self.command).into(),
+            command: self.command,
             command_level: PhantomData,
             arg: PhantomData,
         }
@@ -193,5 +357,36 @@ impl CommandBuilder<InterfaceVport, CmdArg3> {
         self.command
     }
 
-    //TODO missing fields
+    // VLAN externa do QinQ
+    pub fn svlan(mut self, vlan: u16) -> CommandBuilder<InterfaceVport, CmdArg3> {
+        if let Command::ServicePort { svlan, .. } = &mut self.command {
+            *svlan = Some(vlan);
+        }
+        self
+    }
+
+    pub fn ingress(
+        mut self,
+        profile: impl Into<String>,
+    ) -> CommandBuilder<InterfaceVport, CmdArg3> {
+        if let Command::ServicePort { ingress, .. } = &mut self.command {
+            *ingress = Some(profile.into().into());
+        }
+        self
+    }
+
+    pub fn egress(mut self, profile: impl Into<String>) -> CommandBuilder<InterfaceVport, CmdArg3> {
+        if let Command::ServicePort { egress, .. } = &mut self.command {
+            *egress = Some(profile.into().into());
+        }
+        self
+    }
+
+    // Parâmetros que não são interpretados, no fim do comando
+    pub fn extra(mut self, params: impl Into<String>) -> CommandBuilder<InterfaceVport, CmdArg3> {
+        if let Command::ServicePort { extra, .. } = &mut self.command {
+            *extra = Some(params.into().into());
+        }
+        self
+    }
 }
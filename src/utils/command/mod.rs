@@ -1,15 +1,18 @@
 pub mod interface;
 pub mod omci;
+mod syntax;
 
-use std::{fs::File, marker::PhantomData, rc::Rc};
+use std::{borrow::Cow, fs::File, marker::PhantomData, rc::Rc};
 
 use crate::prelude::Result;
 
+use omci::{IngressType, Protocol, WanMode};
+
 use super::{
     configuration::Config,
     configuration::ConfigInfo,
-    olt::Interface,
-    onu::{Onu, OnuService, Vlan},
+    dialect::Dialect,
+    olt::{Interface, Olt},
 };
 
 #[derive(Clone)]
@@ -28,57 +31,124 @@ pub struct CmdArg3;
 #[derive(Clone)]
 pub struct CmdArg4;
 
-// Estrutura que armazena um comando
-#[derive(Clone, Debug)]
-pub struct Command(pub Rc<str>);
+// Comando da OLT. Os comandos conhecidos são guardados já interpretados e
+// os demais ficam como texto em `Raw`. O texto de cada comando vem do
+// `Display` e a interpretação do `FromStr`, no módulo `syntax`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    // `onu N type T [sn S] [pw P]`, dentro da interface gpon_olt
+    OnuAdd {
+        id: u8,
+        onu_type: Rc<str>,
+        sn: Option<Rc<str>>,
+        pw: Option<Rc<str>>,
+    },
+    Name(Rc<str>),
+    Description(Rc<str>),
+    SnBind(Rc<str>),
+    VportMode(Rc<str>),
+    Tcont {
+        id: u8,
+        name: Option<Rc<str>>,
+        profile: Rc<str>,
+    },
+    Gemport {
+        id: u8,
+        name: Option<Rc<str>>,
+        tcont: u8,
+    },
+    Vport {
+        id: u8,
+        map_type: Rc<str>,
+    },
+    VportMap {
+        vport: u8,
+        gemport: u8,
+        vlan: u16,
+    },
+    // O vport só aparece no estilo C300, dentro da interface gpon_onu
+    ServicePort {
+        id: u8,
+        vport: Option<u8>,
+        user_vlan: u16,
+        user_etype: Option<Rc<str>>,
+        vlan: Option<u16>,
+        svlan: Option<u16>,
+        ingress: Option<Rc<str>>,
+        egress: Option<Rc<str>>,
+        extra: Option<Rc<str>>,
+    },
+    // `service N gemport G [vlan V]`, no pon-onu-mng
+    Service {
+        id: u8,
+        gemport: u8,
+        vlan: Option<u16>,
+    },
+    // `wan-ip ipv4 ...` (Titan) ou `wan-ip N ...` (C300)
+    WanIp {
+        index: Option<u8>,
+        mode: WanMode,
+        ip_profile: Option<Rc<str>>,
+        vlan_profile: Option<Rc<str>>,
+        host: Option<u8>,
+    },
+    // `vlan port eth_0/N mode tag vlan V`
+    VlanPort {
+        port: u8,
+        vlan: u16,
+    },
+    SecurityMgmt {
+        id: u8,
+        enable: bool,
+        forward: bool,
+        ingress_type: Option<IngressType>,
+        protocol: Protocol,
+    },
+    Raw(Rc<str>),
+}
 
 // Cria um comando a partir de um texto literal
 impl From<&str> for Command {
     fn from(value: &str) -> Command {
-        Command(value.into())
+        Command::parse(value)
     }
 }
 // Cria um comando a partir de um ponteiro de texto
 impl From<Rc<str>> for Command {
     fn from(value: Rc<str>) -> Command {
-        Command(value)
+        Command::parse(&value)
     }
 }
 // Cria um comando a partir de um texto literal
 impl From<String> for Command {
     fn from(value: String) -> Command {
-        Command(value.into())
-    }
-}
-impl std::fmt::Display for Command {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+        Command::parse(&value)
     }
 }
 
 impl Command {
-    // Devolve o comando como um texto
-    pub fn as_str(&self) -> &str {
-        &self.0
-    }
-    // Devolve o comando como um ponteiro de texto
-    pub fn raw(&self) -> Rc<str> {
-        self.0.clone()
+    // Devolve o texto do comando, sem copiar quando ele não foi interpretado
+    pub fn text(&self) -> Cow<'_, str> {
+        match self {
+            Command::Raw(text) => Cow::Borrowed(text),
+            c => Cow::Owned(c.to_string()),
+        }
     }
 
     // Comando "exit"
     pub fn exit() -> Self {
-        "exit".into()
+        Command::Raw("exit".into())
     }
 
     // Comando "write"
+    #[allow(dead_code)]
     pub fn write() -> Self {
-        "do write".into()
+        Command::Raw("do write".into())
     }
 
     // Comando "end"
     pub fn end() -> Self {
-        "end".into()
+        Command::Raw("end".into())
     }
 
     // Abstração que cria comandos conhecidos
@@ -87,39 +157,19 @@ impl Command {
     }
 
     // Abstração que gera um script de configuração de ONU
-    // baseado nas informações de um arquivo
+    // baseado nas informações de um arquivo. As ONU sem ID recebem o
+    // primeiro ID livre da porta, sem repetir os das ONU já existentes na OLT.
     pub fn onu_script_from_file(
         equipment_info: File,
         vlan: u16,
-        interface: Interface,
+        interface: Option<Interface>,
+        olt: &mut Olt,
     ) -> Result<Config> {
         // Carrega o arquivo em uma estrutura conhecida, caso esteja
         // no formato certo
         let configurations = ConfigInfo::from_file(equipment_info)?;
-        let mut configure_script = Config::default();
-
-        // Itera por cada configuração para criar um script de configuração para cada ONU.
-        for config_info in configurations.iter() {
-            let mut vlan = Vlan::new(vlan);
-            vlan.pppoe(
-                config_info.pppoe_user.clone(),
-                config_info.pppoe_password.clone(),
-            );
-
-            let services = vec![OnuService::new(vlan)];
-            // Cria a ONU
-            let onu = Onu::new(
-                interface.clone(),
-                config_info.model.as_str(),
-                config_info.sn.as_str(),
-                services,
-            );
-            // Gera o script
-            // Adiciona a configuração da ONU no script existente
-            configure_script += onu.configure_script();
-        }
 
-        Ok(configure_script)
+        olt.provision(configurations, vlan, interface.as_ref())
     }
 }
 
@@ -135,7 +185,7 @@ pub struct CommandBuilder<T: Clone, U: Clone> {
 impl CommandBuilder<ConfT, CmdArg0> {
     pub fn new() -> Self {
         CommandBuilder {
-            command: Command::from("configure terminal"),
+            command: Command::Raw("configure terminal".into()),
             command_level: PhantomData,
             arg: PhantomData,
         }
@@ -143,21 +193,19 @@ impl CommandBuilder<ConfT, CmdArg0> {
 
     pub fn interface(self) -> CommandBuilder<Interface, CmdArg0> {
         CommandBuilder {
-            command: Command::from("interface"),
+            command: Command::Raw("interface".into()),
             command_level: PhantomData,
             arg: PhantomData,
         }
     }
 
-    pub fn pon_onu_mng(self, interface: &Interface) -> CommandBuilder<Omci, CmdArg0> {
+    pub fn pon_onu_mng_in(
+        self,
+        dialect: &dyn Dialect,
+        interface: &Interface,
+    ) -> CommandBuilder<Omci, CmdArg0> {
         CommandBuilder {
-            command: format!(
-                "pon-onu-mng gpon_onu-1/{}/{}:{}",
-                interface.slot,
-                interface.port,
-                interface.id.unwrap()
-            )
-            .into(),
+            command: Command::Raw(format!("pon-onu-mng {}", dialect.gpon_onu(interface)).into()),
             command_level: PhantomData,
             arg: PhantomData,
         }
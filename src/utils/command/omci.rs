@@ -3,21 +3,34 @@ use std::marker::PhantomData;
 use crate::utils::command::{CmdArg0, CmdArg1, CmdArg2, CmdArg3, CmdArg4, Command, CommandBuilder};
 
 use super::Omci;
+use crate::utils::dialect::Dialect;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum WanMode {
-    PPPoE { username: String, password: String },
+    PPPoE {
+        username: String,
+        password: String,
+    },
     Dhcp,
+    Static {
+        ip: String,
+        mask: String,
+        gateway: String,
+    },
+    // Sem WAN na ONU, a VLAN é entregue marcada na porta ethernet
+    Bridge {
+        port: u8,
+    },
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum IngressType {
     Iphost(u8),
     Wan,
     Lan,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Protocol {
     Web,
     Telnet,
@@ -31,23 +44,49 @@ pub enum Protocol {
 impl CommandBuilder<Omci, CmdArg0> {
     pub fn service(self, number: u8) -> CommandBuilder<Omci, CmdArg1> {
         CommandBuilder {
-            command: format!("service {number}").into(),
+            command: Command::Service {
+                id: number,
+                gemport: 0,
+                vlan: None,
+            },
             command_level: PhantomData,
             arg: PhantomData,
         }
     }
 
-    pub fn wan_ip(self) -> CommandBuilder<Omci, CmdArg1> {
+    // O modo é definido em seguida, pelo `mode`
+    pub fn wan_ip_in(self, dialect: &dyn Dialect, host: u8) -> CommandBuilder<Omci, CmdArg1> {
         CommandBuilder {
-            command: "wan-ip ipv4".into(),
+            command: Command::WanIp {
+                index: dialect.wan_ip(host),
+                mode: WanMode::Dhcp,
+                ip_profile: None,
+                vlan_profile: None,
+                host: None,
+            },
             command_level: PhantomData,
             arg: PhantomData,
         }
     }
 
+    // Os demais campos são definidos em seguida, do `state` ao `protocol`
     pub fn security_mgmt(self, number: u8) -> CommandBuilder<Omci, CmdArg1> {
         CommandBuilder {
-            command: format!("security-mgmt {number}").into(),
+            command: Command::SecurityMgmt {
+                id: number,
+                enable: false,
+                forward: false,
+                ingress_type: None,
+                protocol: Protocol::Web,
+            },
+            command_level: PhantomData,
+            arg: PhantomData,
+        }
+    }
+
+    pub fn vlan_port(self, port: u8) -> CommandBuilder<Omci, CmdArg1> {
+        CommandBuilder {
+            command: Command::VlanPort { port, vlan: 0 },
             command_level: PhantomData,
             arg: PhantomData,
         }
@@ -58,35 +97,44 @@ impl CommandBuilder<Omci, CmdArg0> {
 
 impl CommandBuilder<Omci, CmdArg1> {
     //service gemport
-    pub fn gemport(self, number: u8) -> CommandBuilder<Omci, CmdArg2> {
+    pub fn gemport(mut self, number: u8) -> CommandBuilder<Omci, CmdArg2> {
+        if let Command::Service { gemport, .. } = &mut self.command {
+            *gemport = number;
+        }
         CommandBuilder {
-            command: format!("{} gemport {number}", self.command).into(),
+            command: self.command,
             command_level: PhantomData,
             arg: PhantomData,
         }
     }
 
     // wan-ip
-    pub fn mode(self, mode: WanMode) -> CommandBuilder<Omci, CmdArg3> {
-        let cmd = match mode {
-            WanMode::Dhcp => "mode dhcp".to_string(),
-            WanMode::PPPoE { username, password } => {
-                format!("mode pppoe username {username} password {password}")
-            }
-        };
+    pub fn mode(mut self, wan_mode: WanMode) -> CommandBuilder<Omci, CmdArg3> {
+        if let Command::WanIp { mode, .. } = &mut self.command {
+            *mode = wan_mode;
+        }
         CommandBuilder {
-            command: format!("{} {cmd}", self.command).into(),
+            command: self.command,
             command_level: PhantomData,
             arg: PhantomData,
         }
     }
 
-    //security-mgmt
-    pub fn state(self, enable: bool) -> CommandBuilder<Omci, CmdArg2> {
-        let state = if enable { "enable" } else { "disable" };
+    // vlan port
+    pub fn tag(mut self, value: u16) -> Command {
+        if let Command::VlanPort { vlan, .. } = &mut self.command {
+            *vlan = value;
+        }
+        self.command
+    }
 
+    //security-mgmt
+    pub fn state(mut self, value: bool) -> CommandBuilder<Omci, CmdArg2> {
+        if let Command::SecurityMgmt { enable, .. } = &mut self.command {
+            *enable = value;
+        }
         CommandBuilder {
-            command: format!("{} state {state}", self.command).into(),
+            command: self.command,
             command_level: PhantomData,
             arg: PhantomData,
         }
@@ -95,22 +143,27 @@ impl CommandBuilder<Omci, CmdArg1> {
 
 impl CommandBuilder<Omci, CmdArg2> {
     //service gemport
+    #[allow(dead_code)]
     pub fn run(self) -> Command {
         self.command
     }
 
-    pub fn vlan(self, vlan: u16) -> Command {
-        Command(format!("{} vlan {vlan}", self.command).into())
+    pub fn vlan(mut self, value: u16) -> Command {
+        if let Command::Service { vlan, .. } = &mut self.command {
+            *vlan = Some(value);
+        }
+        self.command
     }
 
     //wan-ip
 
     //security-mgmt
-    pub fn mode(self, value: bool) -> CommandBuilder<Omci, CmdArg3> {
-        let mode = if value { "forward" } else { "discard" };
-
+    pub fn mode(mut self, value: bool) -> CommandBuilder<Omci, CmdArg3> {
+        if let Command::SecurityMgmt { forward, .. } = &mut self.command {
+            *forward = value;
+        }
         CommandBuilder {
-            command: format!("{} mode {mode}", self.command).into(),
+            command: self.command,
             command_level: PhantomData,
             arg: PhantomData,
         }
@@ -121,22 +174,31 @@ impl CommandBuilder<Omci, CmdArg2> {
 
 impl CommandBuilder<Omci, CmdArg3> {
     //wan-ip
-    pub fn vlan_profile(self, vlan: u16) -> CommandBuilder<Omci, CmdArg4> {
+    pub fn ip_profile(mut self, profile: impl Into<String>) -> CommandBuilder<Omci, CmdArg3> {
+        if let Command::WanIp { ip_profile, .. } = &mut self.command {
+            *ip_profile = Some(profile.into().into());
+        }
+        self
+    }
+
+    pub fn vlan_profile(mut self, profile: impl Into<String>) -> CommandBuilder<Omci, CmdArg4> {
+        if let Command::WanIp { vlan_profile, .. } = &mut self.command {
+            *vlan_profile = Some(profile.into().into());
+        }
         CommandBuilder {
-            command: format!("{} vlan-profile {vlan}", self.command).into(),
+            command: self.command,
             command_level: PhantomData,
             arg: PhantomData,
         }
     }
 
     //security-mgmt
-    pub fn ingress_type(self, r#type: IngressType) -> CommandBuilder<Omci, CmdArg4> {
-        let ingrs = match r#type {
-            IngressType::Iphost(x) => format!("iphost {x}"),
-            _ => format!("{:?}", r#type),
-        };
+    pub fn ingress_type(mut self, r#type: IngressType) -> CommandBuilder<Omci, CmdArg4> {
+        if let Command::SecurityMgmt { ingress_type, .. } = &mut self.command {
+            *ingress_type = Some(r#type);
+        }
         CommandBuilder {
-            command: format!("{} ingress-type {ingrs}", self.command).into(),
+            command: self.command,
             command_level: PhantomData,
             arg: PhantomData,
         }
@@ -145,12 +207,18 @@ impl CommandBuilder<Omci, CmdArg3> {
 
 impl CommandBuilder<Omci, CmdArg4> {
     //wan-ip
-    pub fn host(self, number: u8) -> Command {
-        Command(format!("{} host {number}", self.command).into())
+    pub fn host(mut self, number: u8) -> Command {
+        if let Command::WanIp { host, .. } = &mut self.command {
+            *host = Some(number);
+        }
+        self.command
     }
 
     //security-mgmt
-    pub fn protocol(self, prot: Protocol) -> Command {
-        Command(format!("{} protocol {:?}", self.command, prot).into())
+    pub fn protocol(mut self, prot: Protocol) -> Command {
+        if let Command::SecurityMgmt { protocol, .. } = &mut self.command {
+            *protocol = prot;
+        }
+        self.command
     }
 }
@@ -0,0 +1,494 @@
+use std::{fmt::Display, rc::Rc, str::FromStr};
+
+use crate::prelude::{Error, Result};
+
+use super::{
+    omci::{IngressType, Protocol, WanMode},
+    Command,
+};
+
+// Parte opcional de um comando, como ` name NOME`
+fn optional(key: &str, value: &Option<impl Display>) -> String {
+    value
+        .as_ref()
+        .map_or(String::new(), |v| format!(" {key} {v}"))
+}
+
+impl Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Command::OnuAdd {
+                id,
+                onu_type,
+                sn,
+                pw,
+            } => write!(
+                f,
+                "onu {id} type {onu_type}{}{}",
+                optional("sn", sn),
+                optional("pw", pw)
+            ),
+            Command::Name(name) => write!(f, "name {name}"),
+            Command::Description(description) => write!(f, "description {description}"),
+            Command::SnBind(mode) => write!(f, "sn-bind {mode}"),
+            Command::VportMode(mode) => write!(f, "vport-mode {mode}"),
+            Command::Tcont { id, name, profile } => {
+                write!(f, "tcont {id}{} profile {profile}", optional("name", name))
+            }
+            Command::Gemport { id, name, tcont } => {
+                write!(f, "gemport {id}{} tcont {tcont}", optional("name", name))
+            }
+            Command::Vport { id, map_type } => write!(f, "vport {id} map-type {map_type}"),
+            Command::VportMap {
+                vport,
+                gemport,
+                vlan,
+            } => write!(f, "vport-map {vport} {gemport} vlan {vlan}"),
+            Command::ServicePort {
+                id,
+                vport,
+                user_vlan,
+                user_etype,
+                vlan,
+                svlan,
+                ingress,
+                egress,
+                extra,
+            } => {
+                write!(
+                    f,
+                    "service-port {id}{} user-vlan {user_vlan}{}{}{}{}{}",
+                    optional("vport", vport),
+                    optional("user-etype", user_etype),
+                    optional("vlan", vlan),
+                    optional("svlan", svlan),
+                    optional("ingress", ingress),
+                    optional("egress", egress)
+                )?;
+                match extra {
+                    Some(extra) => write!(f, " {extra}"),
+                    None => Ok(()),
+                }
+            }
+            Command::Service { id, gemport, vlan } => {
+                write!(
+                    f,
+                    "service {id} gemport {gemport}{}",
+                    optional("vlan", vlan)
+                )
+            }
+            Command::WanIp {
+                index,
+                mode,
+                ip_profile,
+                vlan_profile,
+                host,
+            } => {
+                match index {
+                    Some(i) => write!(f, "wan-ip {i} mode ")?,
+                    None => write!(f, "wan-ip ipv4 mode ")?,
+                }
+                match mode {
+                    WanMode::PPPoE { username, password } => {
+                        write!(f, "pppoe username {username} password {password}")?
+                    }
+                    WanMode::Dhcp => write!(f, "dhcp")?,
+                    WanMode::Static { .. } => write!(f, "static")?,
+                    WanMode::Bridge { .. } => write!(f, "bridge")?,
+                }
+                write!(f, "{}", optional("ip-profile", ip_profile))?;
+                if let WanMode::Static { ip, mask, gateway } = mode {
+                    write!(f, " ip-address {ip} mask {mask}")?;
+                    if !gateway.is_empty() {
+                        write!(f, " gateway {gateway}")?;
+                    }
+                }
+                write!(
+                    f,
+                    "{}{}",
+                    optional("vlan-profile", vlan_profile),
+                    optional("host", host)
+                )
+            }
+            Command::VlanPort { port, vlan } => {
+                write!(f, "vlan port eth_0/{port} mode tag vlan {vlan}")
+            }
+            Command::SecurityMgmt {
+                id,
+                enable,
+                forward,
+                ingress_type,
+                protocol,
+            } => {
+                let state = if *enable { "enable" } else { "disable" };
+                let mode = if *forward { "forward" } else { "discard" };
+                write!(
+                    f,
+                    "security-mgmt {id} state {state} mode {mode}{} protocol {protocol}",
+                    optional("ingress-type", ingress_type)
+                )
+            }
+            Command::Raw(text) => write!(f, "{text}"),
+        }
+    }
+}
+
+// Lê os pares `chave valor` opcionais de um comando, na ordem dada,
+// devolvendo o que sobrar
+fn take_pairs<'a>(
+    mut tokens: &'a [&'a str],
+    keys: &[&str],
+) -> (Vec<Option<&'a str>>, &'a [&'a str]) {
+    let mut values = Vec::new();
+    for key in keys {
+        match tokens {
+            [k, v, rest @ ..] if k == key => {
+                values.push(Some(*v));
+                tokens = rest;
+            }
+            _ => values.push(None),
+        }
+    }
+
+    (values, tokens)
+}
+
+fn rc(value: Option<&str>) -> Option<Rc<str>> {
+    value.map(Rc::from)
+}
+
+fn number<T: FromStr>(value: Option<&str>) -> Option<Option<T>> {
+    match value {
+        Some(v) => v.parse().ok().map(Some),
+        None => Some(None),
+    }
+}
+
+impl Command {
+    // Interpreta um comando conhecido. Só aceita o comando interpretado se
+    // ele gerar exatamente o mesmo texto; caso contrário fica como `Raw`.
+    pub(super) fn parse(text: &str) -> Command {
+        match Command::parse_known(text) {
+            Some(command) if command.to_string() == text => command,
+            _ => Command::Raw(text.into()),
+        }
+    }
+
+    fn parse_known(text: &str) -> Option<Command> {
+        let tokens: Vec<&str> = text.split(' ').collect();
+        let rest_of = |prefix: &str| -> Rc<str> { Rc::from(&text[prefix.len()..]) };
+
+        let command = match tokens.as_slice() {
+            ["onu", id, "type", onu_type, rest @ ..] => {
+                let (values, []) = take_pairs(rest, &["sn", "pw"]) else {
+                    return None;
+                };
+                Command::OnuAdd {
+                    id: id.parse().ok()?,
+                    onu_type: (*onu_type).into(),
+                    sn: rc(values[0]),
+                    pw: rc(values[1]),
+                }
+            }
+            ["name", _, ..] => Command::Name(rest_of("name ")),
+            ["description", _, ..] => Command::Description(rest_of("description ")),
+            ["sn-bind", _, ..] => Command::SnBind(rest_of("sn-bind ")),
+            ["vport-mode", _, ..] => Command::VportMode(rest_of("vport-mode ")),
+            ["tcont", id, rest @ ..] => {
+                let (values, []) = take_pairs(rest, &["name", "profile"]) else {
+                    return None;
+                };
+                Command::Tcont {
+                    id: id.parse().ok()?,
+                    name: rc(values[0]),
+                    profile: values[1]?.into(),
+                }
+            }
+            ["gemport", id, rest @ ..] => {
+                let (values, []) = take_pairs(rest, &["name", "tcont"]) else {
+                    return None;
+                };
+                Command::Gemport {
+                    id: id.parse().ok()?,
+                    name: rc(values[0]),
+                    tcont: values[1]?.parse().ok()?,
+                }
+            }
+            ["vport", id, "map-type", map_type] => Command::Vport {
+                id: id.parse().ok()?,
+                map_type: (*map_type).into(),
+            },
+            ["vport-map", vport, gemport, "vlan", vlan] => Command::VportMap {
+                vport: vport.parse().ok()?,
+                gemport: gemport.parse().ok()?,
+                vlan: vlan.parse().ok()?,
+            },
+            ["service-port", id, rest @ ..] => {
+                let (values, rest) = take_pairs(
+                    rest,
+                    &[
+                        "vport",
+                        "user-vlan",
+                        "user-etype",
+                        "vlan",
+                        "svlan",
+                        "ingress",
+                        "egress",
+                    ],
+                );
+                Command::ServicePort {
+                    id: id.parse().ok()?,
+                    vport: number(values[0])?,
+                    user_vlan: values[1]?.parse().ok()?,
+                    user_etype: rc(values[2]),
+                    vlan: number(values[3])?,
+                    svlan: number(values[4])?,
+                    ingress: rc(values[5]),
+                    egress: rc(values[6]),
+                    extra: (!rest.is_empty()).then(|| rest.join(" ").into()),
+                }
+            }
+            ["service", id, "gemport", gemport, rest @ ..] => {
+                let (values, []) = take_pairs(rest, &["vlan"]) else {
+                    return None;
+                };
+                Command::Service {
+                    id: id.parse().ok()?,
+                    gemport: gemport.parse().ok()?,
+                    vlan: number(values[0])?,
+                }
+            }
+            ["wan-ip", index, "mode", mode, rest @ ..] => {
+                let index = match *index {
+                    "ipv4" => None,
+                    i => Some(i.parse().ok()?),
+                };
+                let (credentials, rest) = take_pairs(rest, &["username", "password"]);
+                let (ip_profile, rest) = take_pairs(rest, &["ip-profile"]);
+                let (address, rest) = take_pairs(rest, &["ip-address", "mask", "gateway"]);
+                let (values, []) = take_pairs(rest, &["vlan-profile", "host"]) else {
+                    return None;
+                };
+
+                let mode = match *mode {
+                    "pppoe" => WanMode::PPPoE {
+                        username: credentials[0]?.to_string(),
+                        password: credentials[1]?.to_string(),
+                    },
+                    "dhcp" => WanMode::Dhcp,
+                    "static" => WanMode::Static {
+                        ip: address[0]?.to_string(),
+                        mask: address[1]?.to_string(),
+                        gateway: address[2].unwrap_or_default().to_string(),
+                    },
+                    "bridge" => WanMode::Bridge { port: 1 },
+                    _ => return None,
+                };
+                Command::WanIp {
+                    index,
+                    mode,
+                    ip_profile: rc(ip_profile[0]),
+                    vlan_profile: rc(values[0]),
+                    host: number(values[1])?,
+                }
+            }
+            ["vlan", "port", port, "mode", "tag", "vlan", vlan] => Command::VlanPort {
+                port: port.strip_prefix("eth_0/")?.parse().ok()?,
+                vlan: vlan.parse().ok()?,
+            },
+            ["security-mgmt", id, "state", state, "mode", mode, rest @ ..] => {
+                let (ingress_type, protocol) = match rest {
+                    ["ingress-type", "iphost", n, "protocol", protocol] => {
+                        (Some(IngressType::Iphost(n.parse().ok()?)), protocol)
+                    }
+                    ["ingress-type", t, "protocol", protocol] => (Some(t.parse().ok()?), protocol),
+                    ["protocol", protocol] => (None, protocol),
+                    _ => return None,
+                };
+                Command::SecurityMgmt {
+                    id: id.parse().ok()?,
+                    enable: *state == "enable",
+                    forward: *mode == "forward",
+                    ingress_type,
+                    protocol: protocol.parse().ok()?,
+                }
+            }
+            _ => return None,
+        };
+
+        Some(command)
+    }
+}
+
+impl FromStr for Command {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        Ok(Command::parse(value))
+    }
+}
+
+impl Display for IngressType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IngressType::Iphost(host) => write!(f, "iphost {host}"),
+            IngressType::Wan => write!(f, "wan"),
+            IngressType::Lan => write!(f, "lan"),
+        }
+    }
+}
+
+impl FromStr for IngressType {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "wan" => Ok(IngressType::Wan),
+            "lan" => Ok(IngressType::Lan),
+            _ => Err(Error::Generic(format!(
+                "ingress-type `{value}` desconhecido"
+            ))),
+        }
+    }
+}
+
+impl Display for Protocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Protocol::Web => "web",
+            Protocol::Telnet => "telnet",
+            Protocol::Ssh => "ssh",
+            Protocol::Ftp => "ftp",
+            Protocol::Snmp => "snmp",
+            Protocol::Tr069 => "tr069",
+            Protocol::Https => "https",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl FromStr for Protocol {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let protocol = match value {
+            "web" => Protocol::Web,
+            "telnet" => Protocol::Telnet,
+            "ssh" => Protocol::Ssh,
+            "ftp" => Protocol::Ftp,
+            "snmp" => Protocol::Snmp,
+            "tr069" => Protocol::Tr069,
+            "https" => Protocol::Https,
+            _ => return Err(Error::Generic(format!("Protocolo `{value}` desconhecido"))),
+        };
+
+        Ok(protocol)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::olt::{C3xx, Interface, Titan};
+
+    const KNOWN: &[&str] = &[
+        "onu 1 type ZTE-F670L sn ZTEGC0000001",
+        "onu 101 type ZTE-F601 sn ZTEGC0000002 pw 1234",
+        "name cliente 1",
+        "description Rua A 123",
+        "sn-bind enable sn",
+        "vport-mode manual",
+        "tcont 1 profile 200M",
+        "tcont 2 name t2 profile 1G",
+        "gemport 1 tcont 1",
+        "gemport 2 name g2 tcont 2",
+        "vport 1 map-type vlan",
+        "vport-map 1 1 vlan 100",
+        "service-port 1 user-vlan 100 vlan 100",
+        "service-port 1 vport 1 user-vlan 100 user-etype PPPOE vlan 100",
+        "service-port 2 vport 2 user-vlan 200 vlan 200 svlan 10 ingress UP egress DOWN",
+        "service-port 3 user-vlan 300 vlan 300 description cliente",
+        "service 1 gemport 1 vlan 100",
+        "service 2 gemport 2",
+        "wan-ip ipv4 mode pppoe username joao password 123 vlan-profile 100 host 1",
+        "wan-ip 1 mode dhcp vlan-profile 100 host 1",
+        "wan-ip ipv4 mode static ip-profile IPP ip-address 10.0.0.2 mask 255.255.255.0 vlan-profile VOIP200 host 2",
+        "wan-ip 2 mode static ip-address 10.0.0.2 mask 255.255.255.0 gateway 10.0.0.1 vlan-profile 200 host 2",
+        "vlan port eth_0/2 mode tag vlan 300",
+        "security-mgmt 1 state enable mode forward ingress-type iphost 1 protocol web",
+        "security-mgmt 2 state disable mode discard ingress-type lan protocol ssh",
+        "security-mgmt 1 state enable mode forward protocol web",
+    ];
+
+    #[test]
+    fn known_commands_round_trip() {
+        for line in KNOWN {
+            let command = Command::parse(line);
+            assert!(
+                !matches!(command, Command::Raw(_)),
+                "`{line}` não foi interpretado"
+            );
+            assert_eq!(command.to_string(), *line);
+        }
+    }
+
+    #[test]
+    fn unknown_commands_stay_raw() {
+        for line in [
+            "interface gpon_olt-1/1/1",
+            "encrypt 1 enable downstream",
+            "tcont 1 profile",
+            "wan-ip ipv4 mode dhcp vlan-profile 100 host 1 extra",
+            "security-mgmt 1 state enable mode forward ingress-type foo protocol web",
+            "security-mgmt 1 state enable mode forward protocol Web",
+        ] {
+            assert_eq!(Command::parse(line), Command::Raw(line.into()));
+        }
+    }
+
+    #[test]
+    fn builders_match_parsed_commands() {
+        let onu = Interface::from_str("interface gpon_onu-1/2/1:1").unwrap();
+        let interface = Command::builder()
+            .interface()
+            .gpon_onu_in(&Titan::C600, &onu);
+        let pon_onu_mng = Command::builder().pon_onu_mng_in(&Titan::C600, &onu);
+
+        let built = [
+            interface.clone().tcont(2).name("t2").profile("1G"),
+            interface.clone().gemport(2).name("g2").tcont(2).run(),
+            interface.clone().vport(1).map_type("vlan"),
+            interface.clone().vport_map(1, 1).vlan(100),
+            interface
+                .service_port(1)
+                .vport(1)
+                .user_vlan(100)
+                .user_etype("PPPOE")
+                .vlan(100)
+                .run(),
+            pon_onu_mng.clone().service(1).gemport(1).vlan(100),
+            pon_onu_mng
+                .clone()
+                .wan_ip_in(&C3xx::C300, 2)
+                .mode(WanMode::Static {
+                    ip: "10.0.0.2".to_string(),
+                    mask: "255.255.255.0".to_string(),
+                    gateway: String::new(),
+                })
+                .ip_profile("IPP")
+                .vlan_profile("VOIP200")
+                .host(2),
+            pon_onu_mng.clone().vlan_port(2).tag(300),
+            pon_onu_mng
+                .security_mgmt(1)
+                .state(true)
+                .mode(true)
+                .ingress_type(IngressType::Iphost(1))
+                .protocol(Protocol::Web),
+        ];
+
+        for command in built {
+            assert_eq!(Command::parse(&command.to_string()), command);
+        }
+    }
+}
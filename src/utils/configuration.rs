@@ -1,21 +1,26 @@
+use crate::prelude::{Error, Result};
 use crate::Command;
-use crate::Result;
+use clap::ValueEnum;
+use indexmap::IndexMap;
 use regex::Regex;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::fmt::Display;
 use std::ops::AddAssign;
 use std::sync::Arc;
 use std::{
-    collections::HashMap,
     fs::File,
-    io::{BufRead, BufReader, Write},
+    io::{Read, Write},
+    path::PathBuf,
     str::FromStr,
 };
 
 use super::olt::InterfaceLevel;
 use super::{
-    olt::Interface,
-    onu::{Onu, OnuService, Vlan},
+    dialect::Dialect,
+    olt::{Interface, OnuIdAllocator},
+    onu::{Onu, OnuService, ServicePort, UncfgOnu, Vlan},
+    session::{OltSession, SshTarget},
 };
 
 #[derive(Clone, Debug)]
@@ -24,14 +29,26 @@ pub struct NestedCommand {
     pub nested: Option<Vec<NestedCommand>>,
 }
 
-impl Iterator for NestedCommand {
-    type Item = NestedCommand;
+// Percorre um bloco em profundidade, devolvendo cada comando junto com os
+// blocos que o contêm, do mais externo para o mais interno
+pub struct DepthFirst<'a> {
+    pending: Vec<(Vec<&'a NestedCommand>, &'a NestedCommand)>,
+}
+
+impl<'a> Iterator for DepthFirst<'a> {
+    type Item = (Vec<&'a NestedCommand>, &'a NestedCommand);
+
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(vector) = &self.nested {
-            vector.iter().next().cloned()
-        } else {
-            None
+        let (path, command) = self.pending.pop()?;
+
+        // Os filhos entram invertidos para saírem na ordem do bloco
+        for child in command.iter().rev() {
+            let mut child_path = path.clone();
+            child_path.push(command);
+            self.pending.push((child_path, child));
         }
+
+        Some((path, command))
     }
 }
 
@@ -44,20 +61,87 @@ impl NestedCommand {
         }
     }
 
-    pub fn as_str(&self) -> &str {
-        self.command.as_str()
+    pub fn text(&self) -> Cow<'_, str> {
+        self.command.text()
+    }
+
+    // Comandos aninhados diretamente neste
+    pub fn iter(&self) -> std::slice::Iter<'_, NestedCommand> {
+        self.nested.as_deref().unwrap_or_default().iter()
+    }
+
+    // Este comando e todos os aninhados nele, em profundidade
+    pub fn iter_depth_first(&self) -> DepthFirst<'_> {
+        DepthFirst {
+            pending: vec![(Vec::new(), self)],
+        }
+    }
+
+    // Chama `f` para cada comando do bloco com os blocos que o contêm
+    pub fn walk<F>(&self, mut f: F)
+    where
+        F: FnMut(&[&NestedCommand], &NestedCommand),
+    {
+        for (path, command) in self.iter_depth_first() {
+            f(&path, command);
+        }
+    }
+
+    // Lista os comandos na ordem em que são digitados na OLT, com a
+    // profundidade de cada um e um `exit` ao fim de cada bloco aninhado.
+    pub fn session_commands(&self, depth: usize) -> Vec<(usize, Command)> {
+        let mut result = vec![(depth, self.command.clone())];
+
+        if let Some(n) = &self.nested {
+            for cmd in n {
+                result.extend(cmd.session_commands(depth + 1));
+            }
+            result.push((depth + 1, Command::exit()));
+        }
+
+        result
+    }
+
+    // Desce pelo último comando aninhado até `depth` níveis abaixo deste,
+    // devolvendo também quantos níveis não existiam.
+    fn descend(&mut self, depth: usize) -> (&mut NestedCommand, usize) {
+        let has_children = self.nested.as_ref().is_some_and(|n| !n.is_empty());
+        if depth == 0 || !has_children {
+            return (self, depth);
+        }
+
+        let children = self.nested.get_or_insert_with(Vec::new);
+        let last = children.len() - 1;
+        children[last].descend(depth - 1)
     }
 
-    pub fn raw(&self) -> String {
-        let mut result = format!("{}\n", self.as_str());
+    // Bloco no formato aceito pelo terminal da OLT, saindo de cada modo
+    // com `exit`
+    pub fn cli(&self, depth: usize) -> String {
+        let indent = "  ".repeat(depth);
+        let mut result = format!("{indent}{}\n", self.command);
 
         if let Some(n) = &self.nested {
             for cmd in n {
-                result += "  ";
-                result += &cmd.raw();
+                result += &cmd.cli(depth + 1);
+            }
+            result += &format!("{indent}exit\n");
+        }
+
+        result
+    }
+
+    // Bloco no formato do backup, fechando cada nível com `$`
+    pub fn raw(&self, depth: usize) -> String {
+        let indent = "  ".repeat(depth);
+        let mut result = format!("{indent}{}\n", self.command);
+
+        if let Some(n) = &self.nested {
+            for cmd in n {
+                result += &cmd.raw(depth + 1);
             }
 
-            result += "$\n";
+            result += &format!("{indent}$\n");
         }
 
         result
@@ -71,7 +155,30 @@ impl ConfigField {
     pub fn as_str(&self) -> &str {
         &self.0
     }
+
+    // Deduz o campo do backup a que pertence um comando de primeiro nível
+    pub fn infer(command: &str) -> ConfigField {
+        if command.starts_with("pon-onu-mng ") {
+            return ConfigField::from("xpon");
+        }
+
+        if let Some(name) = command.strip_prefix("interface ") {
+            if name.starts_with("vport") {
+                return ConfigField::from("MSAN");
+            }
+
+            return match Interface::from_str(name).map(|i| i.level) {
+                Ok(InterfaceLevel::GponOlt) | Ok(InterfaceLevel::GponOnu) => {
+                    ConfigField::from("xpon")
+                }
+                _ => ConfigField::from("if-intf"),
+            };
+        }
+
+        ConfigField::default()
+    }
 }
+
 impl Display for ConfigField {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.0)
@@ -90,27 +197,95 @@ impl From<&str> for ConfigField {
     }
 }
 
-#[derive(Debug, Default)]
-pub struct Config(pub HashMap<ConfigField, Vec<NestedCommand>>);
+// Campos na ordem em que aparecem no backup ou em que foram criados
+#[derive(Clone, Debug, Default)]
+pub struct Config(pub IndexMap<ConfigField, Vec<NestedCommand>>);
 
 impl AddAssign for Config {
     fn add_assign(&mut self, rhs: Self) {
         for (key, value) in rhs.0 {
-            if let Some(i) = self.0.get_mut(&key) {
-                i.extend(value);
-            } else {
-                self.0.insert(key, value);
+            let commands = self.0.entry(key).or_default();
+            for command in value {
+                // As ONU da mesma porta ficam em um único bloco gpon_olt, que
+                // já aparece antes das interfaces delas
+                let same_port = commands.iter_mut().find(|c| {
+                    c.nested.is_some()
+                        && c.command == command.command
+                        && Interface::from_str(&c.text())
+                            .is_ok_and(|i| i.level == InterfaceLevel::GponOlt)
+                });
+                match (same_port, command.nested) {
+                    (Some(block), Some(nested)) => {
+                        nested.into_iter().for_each(|n| block.nest(n));
+                    }
+                    (_, nested) => commands.push(NestedCommand {
+                        command: command.command,
+                        nested,
+                    }),
+                }
             }
         }
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize, Default)]
 pub struct ConfigInfo {
     pub sn: String,
-    pub pppoe_user: String,
-    pub pppoe_password: String,
     pub model: String,
+    // pppoe (padrão), dhcp, bridge ou static
+    #[serde(default)]
+    pub mode: Option<String>,
+    #[serde(default)]
+    pub pppoe_user: Option<String>,
+    #[serde(default)]
+    pub pppoe_password: Option<String>,
+    // Endereçamento do modo static
+    #[serde(default)]
+    pub ip: Option<String>,
+    #[serde(default)]
+    pub mask: Option<String>,
+    #[serde(default)]
+    pub gateway: Option<String>,
+    // Porta ethernet do modo bridge
+    #[serde(default)]
+    pub eth_port: Option<u8>,
+    // VLAN do serviço, quando diferente da passada na linha de comando
+    #[serde(default)]
+    pub vlan: Option<u16>,
+    // Interface PON e ID da ONU, quando diferentes dos passados na linha de comando
+    #[serde(default)]
+    pub interface: Option<String>,
+    #[serde(default)]
+    pub onu_id: Option<u8>,
+    // Senha de registro da ONU
+    #[serde(default)]
+    pub password: Option<String>,
+    // Colunas opcionais com a identificação do cliente
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    // Perfis de banda do plano do cliente
+    #[serde(default)]
+    pub upload: Option<String>,
+    #[serde(default)]
+    pub download: Option<String>,
+}
+
+impl From<&UncfgOnu> for ConfigInfo {
+    fn from(value: &UncfgOnu) -> Self {
+        ConfigInfo {
+            sn: value.sn.clone(),
+            model: value.type_guess().unwrap_or_default(),
+            mode: Some("pppoe".to_string()),
+            interface: Some(format!(
+                "gpon_olt-1/{}/{}",
+                value.interface.slot, value.interface.port
+            )),
+            password: value.password.clone(),
+            ..Default::default()
+        }
+    }
 }
 
 impl ConfigInfo {
@@ -124,6 +299,180 @@ impl ConfigInfo {
 
         Ok(infos)
     }
+
+    // Grava as linhas em um arquivo .csv no mesmo formato lido pelo `from_file`
+    pub fn to_file(infos: &[ConfigInfo], file: File) -> Result<()> {
+        let mut writer = csv::Writer::from_writer(file);
+        for info in infos {
+            writer.serialize(info)?;
+        }
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    // Cria as ONU descritas no arquivo. Os IDs escolhidos nas linhas são
+    // reservados antes, e as ONU sem ID recebem o próximo livre da porta.
+    pub fn to_onus(
+        infos: Vec<ConfigInfo>,
+        vlan: u16,
+        interface: Option<&Interface>,
+        allocator: &mut OnuIdAllocator,
+    ) -> Result<Vec<Onu>> {
+        let groups = ConfigInfo::group_by_sn(infos);
+        let with_sn = |sn: &str, error: Error| match error {
+            Error::Generic(reason) => Error::Generic(format!("ONU {sn}: {reason}")),
+            e => e,
+        };
+
+        let mut interfaces = Vec::new();
+        for rows in groups.iter() {
+            let onu_interface = rows[0].onu_interface(interface)?;
+            allocator
+                .reserve(&onu_interface)
+                .map_err(|e| with_sn(&rows[0].sn, e))?;
+            interfaces.push(onu_interface);
+        }
+
+        let mut onus = Vec::new();
+        for (rows, onu_interface) in groups.iter().zip(interfaces) {
+            let onu_interface = match onu_interface.id {
+                Some(_) => onu_interface,
+                None => allocator
+                    .allocate(&onu_interface)
+                    .map_err(|e| with_sn(&rows[0].sn, e))?,
+            };
+            let services = rows
+                .iter()
+                .map(|row| row.service(vlan))
+                .collect::<Result<Vec<OnuService>>>()?;
+            let config_info = &rows[0];
+
+            // Cria a ONU
+            let mut onu = Onu::new(
+                onu_interface,
+                config_info.model.as_str(),
+                Some(config_info.sn.as_str()).filter(|sn| !sn.is_empty()),
+                services,
+            );
+            onu.set_password(config_info.password.as_deref());
+            let settings = onu.settings_mut();
+            settings.name = config_info.name.as_deref().map(Box::from);
+            settings.description = config_info.description.as_deref().map(Box::from);
+            onus.push(onu);
+        }
+
+        Ok(onus)
+    }
+
+    // Agrupa as linhas pelo número de série, mantendo a ordem do arquivo.
+    // Cada linha de uma mesma ONU é um serviço diferente.
+    pub fn group_by_sn(infos: Vec<ConfigInfo>) -> Vec<Vec<ConfigInfo>> {
+        let mut groups: Vec<Vec<ConfigInfo>> = Vec::new();
+        for info in infos {
+            match groups.iter_mut().find(|g| g[0].sn == info.sn) {
+                Some(group) => {
+                    if group[0].model != info.model {
+                        warn!(
+                            "ONU {}: modelo `{}` ignorado, usando `{}`",
+                            info.sn, info.model, group[0].model
+                        );
+                    }
+                    // Os dados da ONU vêm da primeira linha; as seguintes só
+                    // acrescentam serviços
+                    let first = &group[0];
+                    let ignored = [
+                        (
+                            "interface",
+                            info.interface.is_some() && info.interface != first.interface,
+                        ),
+                        (
+                            "onu_id",
+                            info.onu_id.is_some() && info.onu_id != first.onu_id,
+                        ),
+                        ("name", info.name.is_some() && info.name != first.name),
+                        (
+                            "password",
+                            info.password.is_some() && info.password != first.password,
+                        ),
+                    ];
+                    for (column, _) in ignored.iter().filter(|(_, differs)| *differs) {
+                        warn!(
+                            "ONU {}: coluna `{column}` diferente da primeira linha, usando a dela",
+                            info.sn
+                        );
+                    }
+                    group.push(info)
+                }
+                None => groups.push(vec![info]),
+            }
+        }
+
+        groups
+    }
+
+    // Interface da ONU: a da coluna `interface` ou a padrão, com o ID da
+    // coluna `onu_id` quando informado
+    pub fn onu_interface(&self, default: Option<&Interface>) -> Result<Interface> {
+        let interface = match (&self.interface, default) {
+            (Some(i), _) if !i.trim().is_empty() => {
+                Interface::from_str(i.trim()).map_err(|_| {
+                    Error::Generic(format!("ONU {}: interface `{i}` inválida", self.sn))
+                })?
+            }
+            (_, Some(i)) => i.clone(),
+            _ => {
+                return Err(Error::Generic(format!(
+                    "ONU {}: sem a coluna `interface` e sem --interface",
+                    self.sn
+                )))
+            }
+        };
+
+        Ok(match self.onu_id {
+            Some(id) => interface.with_id(id),
+            None => interface,
+        })
+    }
+
+    // Monta o serviço descrito na linha
+    pub fn service(&self, default_vlan: u16) -> Result<OnuService> {
+        let vlan = self.vlan(self.vlan.unwrap_or(default_vlan))?;
+        Ok(OnuService::new(vlan).with_bandwidth(self.upload.as_deref(), self.download.as_deref()))
+    }
+
+    // Monta a VLAN com o modo de WAN pedido na linha
+    pub fn vlan(&self, id: u16) -> Result<Vlan> {
+        let mut vlan = Vlan::new(id);
+        let missing =
+            |column: &str| Error::Generic(format!("ONU {}: coluna `{column}` vazia", self.sn));
+
+        match self.mode.as_deref().unwrap_or("pppoe") {
+            "pppoe" => vlan.pppoe(
+                self.pppoe_user
+                    .clone()
+                    .ok_or_else(|| missing("pppoe_user"))?,
+                self.pppoe_password
+                    .clone()
+                    .ok_or_else(|| missing("pppoe_password"))?,
+            ),
+            "dhcp" => vlan.dhcp(),
+            "static" => vlan.static_ip(
+                self.ip.clone().ok_or_else(|| missing("ip"))?,
+                self.mask.clone().ok_or_else(|| missing("mask"))?,
+                self.gateway.clone().ok_or_else(|| missing("gateway"))?,
+            ),
+            "bridge" => vlan.bridge(self.eth_port.unwrap_or(1)),
+            other => {
+                return Err(Error::Generic(format!(
+                    "ONU {}: modo `{other}` desconhecido",
+                    self.sn
+                )))
+            }
+        }
+
+        Ok(vlan)
+    }
 }
 
 impl From<Command> for NestedCommand {
@@ -135,54 +484,213 @@ impl From<Command> for NestedCommand {
     }
 }
 
-impl From<File> for Config {
-    fn from(value: File) -> Self {
-        let content = BufReader::new(value);
-        let mut result: HashMap<ConfigField, Vec<NestedCommand>> = HashMap::new();
-        let field_pattern = Regex::new(r"!<(?P<name>.*)>").unwrap();
-        let nest_pattern = Regex::new(r"^ {2,}(?P<command>.*)").unwrap();
-
-        let mut buffer = None;
-        for command in content.lines().flatten() {
-            info!("{buffer:?}");
-            info!("{command}");
-            if command.is_empty() {
+impl TryFrom<File> for Config {
+    type Error = Error;
+
+    fn try_from(mut value: File) -> Result<Self> {
+        let mut content = String::new();
+        value.read_to_string(&mut content)?;
+        Config::parse(&content)
+    }
+}
+
+impl FromStr for Config {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        Config::parse(value)
+    }
+}
+
+// Origem de uma configuração: um arquivo de backup ou uma OLT acessada via SSH
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConfigSource {
+    File(PathBuf),
+    Device(SshTarget),
+}
+
+impl FromStr for ConfigSource {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        if value.starts_with("ssh://") {
+            Ok(ConfigSource::Device(SshTarget::from_str(value)?))
+        } else {
+            Ok(ConfigSource::File(PathBuf::from(value)))
+        }
+    }
+}
+
+impl Config {
+    // Carrega a configuração de um arquivo ou diretamente da OLT
+    pub fn load(source: &ConfigSource) -> Result<Config> {
+        match source {
+            ConfigSource::File(path) => Config::try_from(File::open(path)?),
+            ConfigSource::Device(target) => Config::from_device(target),
+        }
+    }
+
+    // Lê o `show running-config` da OLT e o interpreta como um backup
+    pub fn from_device(target: &SshTarget) -> Result<Config> {
+        let mut session = OltSession::open(target)?;
+        let config = session.running_config();
+        session.close()?;
+
+        config
+    }
+
+    // Interpreta um backup, ignorando linhas com problemas recuperáveis
+    pub fn parse(text: &str) -> Result<Config> {
+        let (config, warnings) = Config::parse_with_warnings(text)?;
+        for warning in warnings {
+            warn!("{warning}");
+        }
+
+        Ok(config)
+    }
+
+    // Interpreta um backup e falha no primeiro problema encontrado
+    pub fn parse_strict(text: &str) -> Result<Config> {
+        let (config, mut warnings) = Config::parse_with_warnings(text)?;
+        if warnings.is_empty() {
+            Ok(config)
+        } else {
+            Err(warnings.remove(0))
+        }
+    }
+
+    // Interpreta um backup. Quando o texto não tem os marcadores `!<campo>`,
+    // como na saída do `show running-config`, o campo de cada bloco é
+    // deduzido pelo comando que o abre. Linhas que não se encaixam na
+    // estrutura são mantidas da melhor forma possível e devolvidas como avisos.
+    // Scripts no formato `cli` também são aceitos: a indentação já define os
+    // blocos, então o `configure terminal`, os `exit` e o `end` são ignorados.
+    pub fn parse_with_warnings(text: &str) -> Result<(Config, Vec<Error>)> {
+        let mut result: IndexMap<ConfigField, Vec<NestedCommand>> = IndexMap::new();
+        let mut warnings = Vec::new();
+        let field_pattern = Regex::new(r"^!<(?P<name>.*)>").unwrap();
+
+        let enter_configure = Command::builder().command;
+        let cli = text.lines().map(str::trim).find(|l| !l.is_empty())
+            == Some(enter_configure.text().as_ref());
+        let exit = Command::exit();
+        let end = Command::end();
+
+        let mut buffer: Option<(ConfigField, usize)> = None;
+        let mut inferred = ConfigField::default();
+        // No running-config, o `!` fecha o bloco anterior
+        let mut closed = false;
+        for (index, command) in text.lines().enumerate() {
+            let line = index + 1;
+            debug!("{buffer:?}");
+            debug!("{command}");
+            let trimmed = command.trim();
+            if trimmed.is_empty() {
                 continue;
             }
-            if let Some(s) = field_pattern.captures(&command) {
-                if s["name"].starts_with('/') {
+            if let Some(s) = field_pattern.captures(command) {
+                if let Some(name) = s["name"].strip_prefix('/') {
+                    match &buffer {
+                        Some((open, _)) if open.as_str() == name => {}
+                        Some((open, _)) => warnings.push(Error::parse(
+                            line,
+                            1,
+                            format!("`!</{name}>` fecha o campo `{open}`"),
+                        )),
+                        None => warnings.push(Error::parse(
+                            line,
+                            1,
+                            format!("`!</{name}>` sem `!<{name}>` correspondente"),
+                        )),
+                    }
                     buffer = None;
                 } else {
+                    if let Some((open, open_line)) = &buffer {
+                        warnings.push(Error::parse(
+                            *open_line,
+                            1,
+                            format!("campo `{open}` não foi fechado"),
+                        ));
+                    }
                     let field = ConfigField::from(&s["name"]);
-                    buffer = Some(field.clone());
-                    result.insert(field, Vec::new());
+                    result.entry(field.clone()).or_default();
+                    buffer = Some((field, line));
                 }
                 continue;
             }
 
-            if command.contains('$') {
+            // Fim de bloco no backup (`$`) ou separador do running-config (`!`)
+            if trimmed == "$" {
+                continue;
+            }
+            if trimmed.starts_with('!') {
+                closed = buffer.is_none();
+                continue;
+            }
+            if cli
+                && [&enter_configure, &exit, &end]
+                    .iter()
+                    .any(|c| c.text() == trimmed)
+            {
                 continue;
             }
 
-            let current_command = result.get_mut(&buffer.clone().unwrap()).unwrap();
-            let new_command = NestedCommand::from(Command::from(command.as_str().trim()));
+            let indent = command.len() - command.trim_start().len();
+            if indent % 2 != 0 {
+                warnings.push(Error::parse(
+                    line,
+                    indent + 1,
+                    format!("indentação de {indent} espaços"),
+                ));
+            }
+            // Dois espaços por nível de aninhamento
+            let level = indent / 2;
+
+            if level == 0 {
+                inferred = ConfigField::infer(trimmed);
+                closed = false;
+            }
+            let field = buffer
+                .as_ref()
+                .map(|(f, _)| f.clone())
+                .unwrap_or(inferred.clone());
+
+            let current_command = result.entry(field).or_default();
+            let new_command = NestedCommand::from(Command::from(trimmed));
 
-            if nest_pattern.captures(&command).is_some() {
-                let level: Vec<&str> = command.split("  ").collect();
-                let level = level.len() - 2;
-                let mut current_command = current_command.last_mut().unwrap();
-                for _ in 0..level {
-                    if let Some(ref mut n) = current_command.nested {
-                        current_command = n.last_mut().unwrap();
+            match current_command.last_mut().filter(|_| !closed) {
+                Some(parent) if level > 0 => {
+                    let (parent, missing) = parent.descend(level - 1);
+                    if missing > 0 {
+                        warnings.push(Error::parse(
+                            line,
+                            indent + 1,
+                            format!("{missing} nível(is) de aninhamento sem bloco pai"),
+                        ));
                     }
+                    parent.nest(new_command);
                 }
-                current_command.nest(new_command);
-            } else {
-                current_command.push(new_command);
+                None if level > 0 => {
+                    warnings.push(Error::parse(
+                        line,
+                        indent + 1,
+                        "comando aninhado fora de um bloco".to_string(),
+                    ));
+                    current_command.push(new_command);
+                }
+                _ => current_command.push(new_command),
             }
         }
 
-        Config(result)
+        if let Some((open, open_line)) = buffer {
+            warnings.push(Error::parse(
+                open_line,
+                1,
+                format!("campo `{open}` não foi fechado"),
+            ));
+        }
+
+        Ok((Config(result), warnings))
     }
 }
 
@@ -192,68 +700,213 @@ impl From<Vec<Command>> for Config {
             .iter()
             .map(|c| NestedCommand::from(c.clone()))
             .collect();
-        let mut result = HashMap::new();
+        let mut result = IndexMap::new();
         result.insert(ConfigField::default(), commands);
         Config(result)
     }
 }
 
+// Formatos em que um script pode ser escrito
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum ScriptFormat {
+    // Mesmo formato do backup da OLT, com `!<campo>` e `$`
+    #[default]
+    Backup,
+    // Pronto para colar no terminal, com `exit` ao fim de cada modo
+    Cli,
+}
+
 impl Config {
-    pub fn to_file(&self, mut file: File) -> Result<File> {
+    pub fn render(&self, format: ScriptFormat) -> String {
         let mut script = String::new();
-        for (key, i) in self.0.iter() {
-            let field = (format!("!<{}>", key.0), format!("!</{}>", key.0));
-            script.push_str(&field.0);
-            script.push('\n');
-            for command in i {
-                script.push_str(&command.raw());
+        let fields = self.0.iter().filter(|(_, commands)| !commands.is_empty());
+
+        match format {
+            ScriptFormat::Backup => {
+                for (key, i) in fields {
+                    script.push_str(&format!("!<{}>\n", key.0));
+                    for command in i {
+                        script.push_str(&command.raw(0));
+                    }
+                    script.push_str(&format!("!</{}>\n", key.0));
+                }
+            }
+            ScriptFormat::Cli => {
+                script.push_str(&format!("{}\n", Command::builder().command));
+                for command in fields.flat_map(|(_, i)| i) {
+                    script.push_str(&command.cli(0));
+                    if command.nested.is_some() {
+                        script.push_str("!\n");
+                    }
+                }
+                script.push_str(&format!("{}\n", Command::end()));
             }
-            script.push_str(&field.1);
-            script.push('\n');
         }
 
-        writeln!(file, "{}", script)?;
+        script
+    }
+
+    pub fn to_file(&self, mut file: File, format: ScriptFormat) -> Result<File> {
+        writeln!(file, "{}", self.render(format))?;
         Ok(file)
     }
 
+    // Todos os comandos da configuração, em profundidade, com os blocos
+    // que contêm cada um
+    pub fn iter_depth_first(&self) -> impl Iterator<Item = (Vec<&NestedCommand>, &NestedCommand)> {
+        self.0.values().flatten().flat_map(|c| c.iter_depth_first())
+    }
+
+    // Coloca os campos na ordem canônica do modelo. Os campos que o modelo
+    // não conhece vão para o fim, na ordem em que estavam.
+    pub fn sort(&mut self, dialect: &dyn Dialect) {
+        let order = dialect.section_order();
+        let position = |field: &ConfigField| {
+            order
+                .iter()
+                .position(|f| *f == field.as_str())
+                .unwrap_or(order.len())
+        };
+
+        self.0.sort_by(|a, _, b, _| position(a).cmp(&position(b)));
+    }
+
+    // Comandos a serem enviados à OLT para aplicar esta configuração,
+    // do `configure terminal` até o `end`.
+    pub fn session_commands(&self) -> Vec<(usize, Command)> {
+        let mut result = vec![(0, Command::builder().command)];
+
+        for commands in self.0.values() {
+            for command in commands {
+                result.extend(command.session_commands(1));
+            }
+        }
+
+        result.push((0, Command::end()));
+        result
+    }
+
+    // Conta as interfaces PON que não estão na sintaxe do modelo informado
+    pub fn foreign_interfaces(&self, dialect: &dyn Dialect) -> usize {
+        let Some(field) = self.0.get(&ConfigField::from("xpon")) else {
+            return 0;
+        };
+
+        field
+            .iter()
+            .filter_map(|c| Some((c, Interface::from_str(&c.text()).ok()?)))
+            .filter(|(c, i)| {
+                let expected = match i.level {
+                    InterfaceLevel::GponOlt => dialect.gpon_olt(i),
+                    InterfaceLevel::GponOnu => dialect.gpon_onu(i),
+                    _ => return false,
+                };
+                c.text() != format!("interface {expected}")
+            })
+            .count()
+    }
+
     pub fn extract_onu(&self) -> Vec<Onu> {
         let mut onu_instances: Vec<Onu> = Vec::new();
-        let creation_pattern = Regex::new(
-            r"^onu (?P<id>[1-9]|[1-9][0-9]|1[0-2][0-9]) type (?P<type>.*) sn (?P<sn>.*)$",
-        )
-        .unwrap();
+        let mut service_ports: Vec<(Interface, ServicePort)> = Vec::new();
 
-        let field = self.0.get(&ConfigField::from("xpon")).unwrap();
-        //.unwrap_or(self.0.get(&ConfigField::default()).unwrap());
+        let Some(field) = self.0.get(&ConfigField::from("xpon")) else {
+            return onu_instances;
+        };
 
         for c in field {
-            if let Ok(i) = Interface::from_str(c.as_str()) {
+            if let Ok(i) = Interface::from_str(&c.text()) {
                 if i.level == InterfaceLevel::GponOlt {
-                    if let Some(inter) = &c.nested {
-                        for onu in inter {
-                            if let Some(o) = creation_pattern.captures(onu.as_str()) {
-                                let id = o["id"].parse::<u8>().unwrap();
-                                let interface = i.with_id(id);
-                                let new_onu =
-                                    Onu::new(interface, &o["type"], &o["sn"], Vec::default());
-                                onu_instances.push(new_onu);
+                    for onu in c.iter() {
+                        if let Command::OnuAdd {
+                            id,
+                            onu_type,
+                            sn,
+                            pw,
+                        } = &onu.command
+                        {
+                            let interface = i.with_id(*id);
+                            let mut new_onu =
+                                Onu::new(interface, onu_type, sn.as_deref(), Vec::default());
+                            new_onu.set_password(pw.as_deref());
+                            onu_instances.push(new_onu);
+                        } else if onu.text().starts_with("onu ") {
+                            // LOID e outras formas de autenticação ainda não
+                            // são interpretadas
+                            warn!("`{}` em `{}` não foi migrada", onu.command, c.command);
+                        }
+                    }
+                } else if i.level == InterfaceLevel::GponOnu {
+                    let Some(current_onu) = onu_instances
+                        .iter_mut()
+                        .find(|o| o.interface().same_onu(&i))
+                    else {
+                        warn!("`{}` não corresponde a nenhuma ONU criada", c.command);
+                        continue;
+                    };
+                    for infos in c.iter() {
+                        if let Command::ServicePort { .. } = infos.command {
+                            match ServicePort::parse(&infos.command, None) {
+                                Ok(sp) => service_ports.push((i.clone(), sp)),
+                                Err(_) => {
+                                    current_onu.settings_mut().other.push(infos.command.clone())
+                                }
                             }
+                        } else {
+                            current_onu.settings_mut().parse_command(&infos.command);
                         }
                     }
                 } else if i.level == InterfaceLevel::PonOnuMng {
                     info!("{}", c.command);
-                    let mut services = Vec::new();
-                    let mut current_onu = onu_instances
+                    let Some(current_onu) = onu_instances
                         .iter_mut()
-                        .filter(|o| o.interface() == &i)
-                        .collect::<Vec<&mut Onu>>();
-                    let current_onu = current_onu.first_mut().unwrap();
-                    if let Some(value) = &c.nested {
-                        for infos in value {
-                            let vlan = Vlan::try_from(&infos.command);
-                            if let Ok(v) = vlan {
-                                services.push(OnuService::new(v));
+                        .find(|o| o.interface().same_onu(&i))
+                    else {
+                        warn!("`{}` não corresponde a nenhuma ONU criada", c.command);
+                        continue;
+                    };
+
+                    // Primeiro os serviços, que definem as VLAN, depois as WAN
+                    let mut services = Vec::new();
+                    let mut wans = Vec::new();
+                    let mut other = Vec::new();
+                    for infos in c.iter() {
+                        if let Command::Service {
+                            vlan: Some(id),
+                            gemport,
+                            ..
+                        } = infos.command
+                        {
+                            let mut service = OnuService::new(Vlan::new(id));
+                            service.gemport = Some(gemport);
+                            services.push(service);
+                        } else if let Ok(wan) = Vlan::parse_wan(&infos.command) {
+                            wans.push(wan);
+                        } else {
+                            other.push(infos.command.clone());
+                        }
+                    }
+                    current_onu.settings_mut().mng_other = Some(other);
+
+                    for (wan, host) in wans {
+                        let index = services
+                            .iter()
+                            .position(|s| s.vlan.id == wan.id && s.vlan.service.is_none())
+                            .or_else(|| {
+                                let index = usize::from(host).saturating_sub(1);
+                                services
+                                    .get(index)
+                                    .filter(|s| s.vlan.service.is_none())
+                                    .map(|_| index)
+                            });
+                        match index {
+                            Some(index) => {
+                                let vlan = &mut services[index].vlan;
+                                vlan.service = wan.service;
+                                vlan.profile = wan.profile;
+                                vlan.ip_profile = wan.ip_profile;
                             }
+                            None => services.push(OnuService::new(wan)),
                         }
                     }
 
@@ -262,6 +915,230 @@ impl Config {
             }
         }
 
+        // service-port no estilo C600, dentro das interfaces vport
+        let vport_blocks = self.0.get(&ConfigField::from("MSAN"));
+        for c in vport_blocks.into_iter().flatten() {
+            let text = c.text();
+            let Some(name) = text.strip_prefix("interface ") else {
+                continue;
+            };
+            if let Ok((i, vport)) = Interface::from_vport(name) {
+                let mut other = Vec::new();
+                for infos in c.iter() {
+                    match ServicePort::parse(&infos.command, Some(vport)) {
+                        Ok(sp) => service_ports.push((i.clone(), sp)),
+                        Err(_) => other.push((vport, infos.command.clone())),
+                    }
+                }
+
+                // O que não for service-port fica guardado como está na ONU
+                match onu_instances
+                    .iter_mut()
+                    .find(|o| o.interface().same_onu(&i))
+                {
+                    Some(onu) => onu.settings_mut().vport_other.extend(other),
+                    None if !other.is_empty() => {
+                        warn!("`{}` não corresponde a nenhuma ONU criada", c.command)
+                    }
+                    None => {}
+                }
+            }
+        }
+
+        for (i, sp) in service_ports {
+            match onu_instances
+                .iter_mut()
+                .find(|o| o.interface().same_onu(&i))
+            {
+                Some(onu) => onu.attach_service_port(sp),
+                None => warn!(
+                    "service-port {} de uma ONU inexistente em 1/{}/{}",
+                    sp.id, i.slot, i.port
+                ),
+            }
+        }
+
         onu_instances
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::olt::{C3xx, Titan};
+
+    // Backup com uma ONU e o pon-onu-mng dado, na sintaxe do modelo
+    fn backup(dialect: &dyn Dialect, pon_onu_mng: &[&str]) -> String {
+        let onu = Interface::from_str("interface gpon_onu-1/2/1:1").unwrap();
+        let mut text = format!(
+            "!<xpon>\ninterface {}\n  onu 1 type ZTE-F670L sn ZTEGC0000001\n$\n",
+            dialect.gpon_olt(&onu)
+        );
+        text += &format!("pon-onu-mng {}\n", dialect.gpon_onu(&onu));
+        for line in pon_onu_mng {
+            text += &format!("  {line}\n");
+        }
+        text + "$\n!</xpon>\n"
+    }
+
+    // Extrai a ONU do backup e gera o pon-onu-mng dela de novo
+    fn round_trip(dialect: &dyn Dialect, pon_onu_mng: &[&str]) -> Vec<String> {
+        let config = Config::from_str(&backup(dialect, pon_onu_mng)).unwrap();
+        let onus = config.extract_onu();
+        assert_eq!(onus.len(), 1);
+
+        let script = onus[0].configure_script_for(dialect);
+        script.0[&ConfigField::from("xpon")]
+            .iter()
+            .find(|c| c.text().starts_with("pon-onu-mng"))
+            .unwrap()
+            .iter()
+            .map(|c| c.text().to_string())
+            .collect()
+    }
+
+    // O bloco gerado tem as mesmas linhas do original, sem perder nem
+    // acrescentar nenhuma
+    fn assert_kept(dialect: &dyn Dialect, pon_onu_mng: &[&str]) {
+        let mut generated = round_trip(dialect, pon_onu_mng);
+        let mut original: Vec<String> = pon_onu_mng.iter().map(|l| l.to_string()).collect();
+        generated.sort();
+        original.sort();
+        assert_eq!(generated, original);
+    }
+
+    #[test]
+    fn wan_pppoe_titan() {
+        assert_kept(
+            &Titan::C600,
+            &[
+                "service 1 gemport 1 vlan 100",
+                "wan-ip ipv4 mode pppoe username joao password 123 vlan-profile 100 host 1",
+            ],
+        );
+    }
+
+    #[test]
+    fn wan_dhcp_c300() {
+        assert_kept(
+            &C3xx::C300,
+            &[
+                "service 1 gemport 1 vlan 100",
+                "wan-ip 1 mode dhcp vlan-profile 100 host 1",
+            ],
+        );
+    }
+
+    #[test]
+    fn wan_static_with_ip_profile() {
+        assert_kept(
+            &Titan::C600,
+            &[
+                "service 1 gemport 1 vlan 100",
+                "service 2 gemport 2 vlan 200",
+                "wan-ip ipv4 mode dhcp vlan-profile 100 host 1",
+                "wan-ip ipv4 mode static ip-profile IPP ip-address 10.0.0.2 mask 255.255.255.0 vlan-profile VOIP200 host 2",
+            ],
+        );
+    }
+
+    #[test]
+    fn wan_static_with_gateway_c300() {
+        assert_kept(
+            &C3xx::C300,
+            &[
+                "service 1 gemport 1 vlan 100",
+                "wan-ip 1 mode static ip-address 10.0.0.2 mask 255.255.255.0 gateway 10.0.0.1 vlan-profile 100 host 1",
+            ],
+        );
+    }
+
+    #[test]
+    fn unparsed_lines_are_kept() {
+        assert_kept(
+            &Titan::C600,
+            &[
+                "service 1 gemport 1 vlan 100",
+                "wan-ip ipv4 mode pppoe username joao password 123 vlan-profile 100 host 1",
+                "security-mgmt 1 state enable mode forward protocol web",
+                "ssid ctrl wifi_0/1 name casa",
+            ],
+        );
+    }
+
+    #[test]
+    fn cli_script_parses_like_backup() {
+        let config = Config::from_str(&backup(
+            &Titan::C600,
+            &[
+                "service 1 gemport 1 vlan 100",
+                "wan-ip ipv4 mode dhcp vlan-profile 100 host 1",
+            ],
+        ))
+        .unwrap();
+        let script = config.extract_onu()[0].configure_script_for(&Titan::C600);
+
+        let cli = Config::from_str(&script.render(ScriptFormat::Cli)).unwrap();
+        assert_eq!(
+            cli.render(ScriptFormat::Backup),
+            script.render(ScriptFormat::Backup)
+        );
+    }
+
+    #[test]
+    fn nested_blocks_keep_their_depth() {
+        let backup = "!<router>\nrouter ospf 1\n  area 0\n    network 10.0.0.0 0.0.0.255\n  $\n$\n!</router>\n";
+        let config = Config::from_str(backup).unwrap();
+        assert_eq!(config.render(ScriptFormat::Backup), backup);
+
+        let cli = Config::from_str(&config.render(ScriptFormat::Cli)).unwrap();
+        assert_eq!(
+            cli.render(ScriptFormat::Backup),
+            backup.replace("router>", "raw>")
+        );
+    }
+
+    #[test]
+    fn separator_closes_the_block() {
+        let running = "pon-onu-mng gpon_onu-1/2/1:1\n  wan-ip ipv4 mode dhcp vlan-profile 100 host 1\n!\n    orphan\nhostname OLT\n";
+        let (config, warnings) = Config::parse_with_warnings(running).unwrap();
+        assert_eq!(warnings.len(), 1);
+
+        let mng = &config.0[&ConfigField::from("xpon")][0];
+        assert_eq!(mng.iter().count(), 1);
+        assert!(mng.iter().all(|c| c.nested.is_none()));
+        assert!(config
+            .iter_depth_first()
+            .any(|(path, c)| path.is_empty() && c.text() == "orphan"));
+    }
+
+    #[test]
+    fn password_only_onu() {
+        let config = Config::from_str(
+            "!<xpon>\ninterface gpon_olt-1/2/1\n  onu 2 type ZTE-F601 pw 12345\n  onu 3 type ZTE-F601 loid ABC123\n$\n!</xpon>\n",
+        )
+        .unwrap();
+        let onus = config.extract_onu();
+        assert_eq!(onus.len(), 1);
+
+        let script = onus[0].configure_script_for(&Titan::C600);
+        let interface_olt = &script.0[&ConfigField::from("xpon")][0];
+        assert_eq!(
+            interface_olt.iter().next().unwrap().text(),
+            "onu 2 type ZTE-F601 pw 12345"
+        );
+    }
+
+    #[test]
+    fn bridge() {
+        for dialect in [&Titan::C600 as &dyn Dialect, &C3xx::C300] {
+            assert_kept(
+                dialect,
+                &[
+                    "service 1 gemport 1 vlan 300",
+                    "vlan port eth_0/2 mode tag vlan 300",
+                ],
+            );
+        }
+    }
+}
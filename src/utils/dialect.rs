@@ -0,0 +1,118 @@
+use super::olt::{C3xx, Interface, OltModel, Titan};
+
+// Diferenças de sintaxe entre as linhas de OLT da ZTE
+pub trait Dialect {
+    // Nome da interface PON da OLT
+    fn gpon_olt(&self, interface: &Interface) -> String;
+
+    // Nome da interface de uma ONU
+    fn gpon_onu(&self, interface: &Interface) -> String;
+
+    // Índice do comando wan-ip de um host: `wan-ip N` ou, sem índice,
+    // `wan-ip ipv4`
+    fn wan_ip(&self, host: u8) -> Option<u8>;
+
+    // Indica se os service-port ficam dentro da interface gpon_onu, em vez
+    // de uma interface vport para cada serviço
+    fn service_port_in_onu(&self) -> bool;
+
+    // Indica se a interface gpon_onu usa vport-mode, vport e vport-map
+    fn has_vport(&self) -> bool;
+
+    // Ordem dos campos no script: as ONU precisam ser criadas antes de
+    // entrar na interface e no pon-onu-mng delas
+    fn section_order(&self) -> &'static [&'static str];
+}
+
+// C300, C320 e C350: `gpon-olt_1/x/y`, service-port na interface da ONU
+impl Dialect for C3xx {
+    fn gpon_olt(&self, interface: &Interface) -> String {
+        format!("gpon-olt_1/{}/{}", interface.slot, interface.port)
+    }
+
+    fn gpon_onu(&self, interface: &Interface) -> String {
+        format!(
+            "gpon-onu_1/{}/{}:{}",
+            interface.slot,
+            interface.port,
+            interface.id.unwrap()
+        )
+    }
+
+    fn wan_ip(&self, host: u8) -> Option<u8> {
+        Some(host)
+    }
+
+    fn service_port_in_onu(&self) -> bool {
+        true
+    }
+
+    fn has_vport(&self) -> bool {
+        false
+    }
+
+    fn section_order(&self) -> &'static [&'static str] {
+        &[
+            "system-config",
+            "shelf",
+            "card",
+            "vlan",
+            "gpon",
+            "if-intf",
+            "xpon",
+            "raw",
+        ]
+    }
+}
+
+// Linha Titan: `gpon_olt-1/x/y`, service-port nas interfaces vport
+impl Dialect for Titan {
+    fn gpon_olt(&self, interface: &Interface) -> String {
+        format!("gpon_olt-1/{}/{}", interface.slot, interface.port)
+    }
+
+    fn gpon_onu(&self, interface: &Interface) -> String {
+        format!(
+            "gpon_onu-1/{}/{}:{}",
+            interface.slot,
+            interface.port,
+            interface.id.unwrap()
+        )
+    }
+
+    fn wan_ip(&self, _host: u8) -> Option<u8> {
+        None
+    }
+
+    fn service_port_in_onu(&self) -> bool {
+        false
+    }
+
+    fn has_vport(&self) -> bool {
+        true
+    }
+
+    fn section_order(&self) -> &'static [&'static str] {
+        &[
+            "system-config",
+            "shelf",
+            "card",
+            "vlan",
+            "gpon",
+            "if-intf",
+            "xpon",
+            "MSAN",
+            "raw",
+        ]
+    }
+}
+
+impl OltModel {
+    // Sintaxe de comandos usada pelo modelo
+    pub fn dialect(&self) -> &dyn Dialect {
+        match self {
+            OltModel::Titan(t) => t,
+            OltModel::C3xx(c) => c,
+        }
+    }
+}
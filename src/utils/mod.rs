@@ -1,4 +1,6 @@
 pub mod command;
 pub mod configuration;
+pub mod dialect;
 pub mod olt;
 pub mod onu;
+pub mod session;
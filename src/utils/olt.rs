@@ -1,9 +1,25 @@
 use crate::prelude::{Error, Result};
 
-use super::{command::Command, configuration::Config, onu::Onu};
+use super::{
+    command::Command,
+    configuration::{Config, ConfigInfo, ConfigSource},
+    dialect::Dialect,
+    onu::Onu,
+    session::OltSession,
+};
 use clap::Parser;
 use regex::Regex;
-use std::{rc::Rc, str::FromStr, sync::Arc};
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Display,
+    fs,
+    ops::RangeInclusive,
+    path::Path,
+    rc::Rc,
+    str::FromStr,
+    sync::Arc,
+};
 
 #[derive(Parser, Debug, Clone, Eq, Hash, PartialEq, Default)]
 pub struct Interface {
@@ -21,6 +37,232 @@ pub enum InterfaceLevel {
     Other(Arc<str>),
 }
 
+// Controle dos IDs de ONU em uso em cada porta PON
+#[derive(Debug, Default)]
+pub struct OnuIdAllocator {
+    used: HashMap<(u8, u8), BTreeSet<u8>>,
+    max: u8,
+}
+
+impl OnuIdAllocator {
+    pub fn new(max: u8) -> OnuIdAllocator {
+        OnuIdAllocator {
+            used: HashMap::new(),
+            max,
+        }
+    }
+
+    // Considera os IDs das ONU já provisionadas na OLT
+    pub fn with_existing(max: u8, interfaces: &[Interface]) -> OnuIdAllocator {
+        let mut allocator = OnuIdAllocator::new(max);
+        for interface in interfaces {
+            if let Some(id) = interface.id {
+                allocator
+                    .used
+                    .entry((interface.slot, interface.port))
+                    .or_default()
+                    .insert(id);
+            }
+        }
+
+        allocator
+    }
+
+    // Marca como usado um ID escolhido manualmente
+    pub fn reserve(&mut self, interface: &Interface) -> Result<()> {
+        let Some(id) = interface.id else {
+            return Ok(());
+        };
+        if id == 0 || id > self.max {
+            return Err(Error::Generic(format!(
+                "ID {id} fora do limite de 1 a {} em gpon_olt-1/{}/{}",
+                self.max, interface.slot, interface.port
+            )));
+        }
+
+        let used = self
+            .used
+            .entry((interface.slot, interface.port))
+            .or_default();
+        if !used.insert(id) {
+            return Err(Error::Generic(format!(
+                "ID {id} já está em uso em gpon_olt-1/{}/{}",
+                interface.slot, interface.port
+            )));
+        }
+
+        Ok(())
+    }
+
+    // Devolve a interface com o menor ID livre da porta
+    pub fn allocate(&mut self, interface: &Interface) -> Result<Interface> {
+        let used = self
+            .used
+            .entry((interface.slot, interface.port))
+            .or_default();
+        let id = (1..=self.max)
+            .find(|id| !used.contains(id))
+            .ok_or(Error::Generic(format!(
+                "A porta gpon_olt-1/{}/{} já tem {} ONU",
+                interface.slot, interface.port, self.max
+            )))?;
+        used.insert(id);
+
+        Ok(interface.with_id(id))
+    }
+}
+
+// Troca de slot e porta das ONU na migração entre chassis, lida de um
+// arquivo TOML no formato:
+//
+// [ports]
+// "gpon-olt_1/2/1" = "gpon_olt-1/1/1"
+#[derive(Debug, Default)]
+pub struct PortMap(HashMap<(u8, u8), (u8, u8)>);
+
+#[derive(Deserialize)]
+struct PortMapFile {
+    ports: HashMap<String, String>,
+}
+
+impl FromStr for PortMap {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let file: PortMapFile = toml::from_str(value)?;
+
+        let mut map = HashMap::new();
+        for (old, new) in file.ports.iter() {
+            let parse = |i: &str| {
+                Interface::from_str(i)
+                    .map_err(|_| Error::Generic(format!("Porta `{i}` inválida no mapa")))
+            };
+            let (old, new) = (parse(old)?, parse(new)?);
+            map.insert((old.slot, old.port), (new.slot, new.port));
+        }
+
+        Ok(PortMap(map))
+    }
+}
+
+impl PortMap {
+    pub fn from_file(path: &Path) -> Result<PortMap> {
+        PortMap::from_str(&fs::read_to_string(path)?)
+    }
+
+    // Move as ONU para as portas novas. Falha, sem alterar nenhuma, se
+    // alguma ONU estiver em uma porta sem mapeamento.
+    pub fn apply(&self, onus: &mut [Onu]) -> Result<()> {
+        let mut missing: Vec<String> = Vec::new();
+        for onu in onus.iter() {
+            let interface = onu.interface();
+            let port = format!("1/{}/{}", interface.slot, interface.port);
+            if !self.0.contains_key(&(interface.slot, interface.port)) && !missing.contains(&port) {
+                missing.push(port);
+            }
+        }
+
+        if !missing.is_empty() {
+            return Err(Error::Generic(format!(
+                "Portas sem mapeamento: {}",
+                missing.join(", ")
+            )));
+        }
+
+        for onu in onus.iter_mut() {
+            let interface = onu.interface();
+            let (slot, port) = self.0[&(interface.slot, interface.port)];
+            onu.move_to(slot, port);
+        }
+
+        Ok(())
+    }
+}
+
+// Troca de VLAN das ONU na migração, lida de um arquivo TOML. As trocas
+// de `[ports]` valem só para a porta PON de origem e têm prioridade sobre
+// as de `[vlans]`:
+//
+// [vlans]
+// 100 = 200
+//
+// [ports."gpon-olt_1/2/1"]
+// 100 = 300
+#[derive(Debug, Default)]
+pub struct VlanMap {
+    vlans: HashMap<u16, u16>,
+    ports: HashMap<(u8, u8), HashMap<u16, u16>>,
+}
+
+#[derive(Deserialize)]
+struct VlanMapFile {
+    #[serde(default)]
+    vlans: HashMap<String, u16>,
+    #[serde(default)]
+    ports: HashMap<String, HashMap<String, u16>>,
+}
+
+impl FromStr for VlanMap {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let file: VlanMapFile = toml::from_str(value)?;
+
+        let parse_vlans = |vlans: &HashMap<String, u16>| -> Result<HashMap<u16, u16>> {
+            let mut map = HashMap::new();
+            for (old, new) in vlans.iter() {
+                let old = old
+                    .parse()
+                    .map_err(|_| Error::Generic(format!("VLAN `{old}` inválida no mapa")))?;
+                map.insert(old, *new);
+            }
+            Ok(map)
+        };
+
+        let mut ports = HashMap::new();
+        for (port, vlans) in file.ports.iter() {
+            let interface = Interface::from_str(port)
+                .map_err(|_| Error::Generic(format!("Porta `{port}` inválida no mapa")))?;
+            ports.insert((interface.slot, interface.port), parse_vlans(vlans)?);
+        }
+
+        Ok(VlanMap {
+            vlans: parse_vlans(&file.vlans)?,
+            ports,
+        })
+    }
+}
+
+impl VlanMap {
+    pub fn from_file(path: &Path) -> Result<VlanMap> {
+        VlanMap::from_str(&fs::read_to_string(path)?)
+    }
+
+    // Troca as VLAN das ONU, devolvendo quantas ONU tiveram cada troca
+    pub fn apply(&self, onus: &mut [Onu]) -> BTreeMap<(u16, u16), usize> {
+        let mut summary = BTreeMap::new();
+        for onu in onus.iter_mut() {
+            let interface = onu.interface();
+            let map = match self.ports.get(&(interface.slot, interface.port)) {
+                Some(port) => {
+                    let mut map = self.vlans.clone();
+                    map.extend(port.iter());
+                    map
+                }
+                None => self.vlans.clone(),
+            };
+
+            for change in onu.translate_vlans(&map) {
+                *summary.entry(change).or_default() += 1;
+            }
+        }
+
+        summary
+    }
+}
+
+// Modelo em memória de uma OLT: a configuração carregada e as ONU
+// provisionadas nela
 pub struct Olt {
     model: OltModel,
     interfaces: Rc<[Interface]>,
@@ -32,13 +274,292 @@ impl From<OltModel> for Olt {
     fn from(value: OltModel) -> Self {
         Olt {
             model: value,
-            interfaces: Rc::new([Interface::default()]),
+            interfaces: Rc::new([]),
             onu: Default::default(),
             configuration: Default::default(),
         }
     }
 }
 
+impl Olt {
+    // Monta a OLT a partir de uma configuração já interpretada
+    pub fn new(model: OltModel, configuration: Config) -> Olt {
+        let mut interfaces: Vec<Interface> = Vec::new();
+        for c in configuration.0.values().flatten() {
+            if let Ok(i) = Interface::from_str(&c.text()) {
+                if i.level == InterfaceLevel::GponOlt && !interfaces.contains(&i) {
+                    interfaces.push(i);
+                }
+            }
+        }
+
+        Olt {
+            model,
+            interfaces: interfaces.into(),
+            onu: configuration.extract_onu(),
+            configuration,
+        }
+    }
+
+    // Carrega a OLT de um backup ou diretamente do equipamento
+    pub fn load(model: OltModel, source: &ConfigSource) -> Result<Olt> {
+        Ok(Olt::new(model, Config::load(source)?))
+    }
+
+    // Carrega a OLT pelo `show running-config` de uma sessão aberta
+    pub fn from_session(model: OltModel, session: &mut OltSession) -> Result<Olt> {
+        Ok(Olt::new(model, session.running_config()?))
+    }
+
+    pub fn model(&self) -> OltModel {
+        self.model
+    }
+
+    pub fn dialect(&self) -> &dyn Dialect {
+        self.model.dialect()
+    }
+
+    // Portas PON declaradas na configuração
+    pub fn interfaces(&self) -> &[Interface] {
+        &self.interfaces
+    }
+
+    pub fn onus(&self) -> &[Onu] {
+        &self.onu
+    }
+
+    pub fn into_onus(self) -> Vec<Onu> {
+        self.onu
+    }
+
+    pub fn configuration(&self) -> &Config {
+        &self.configuration
+    }
+
+    pub fn into_configuration(self) -> Config {
+        self.configuration
+    }
+
+    // Quantidade de ONU em cada porta PON, por slot e porta
+    pub fn onus_per_port(&self) -> BTreeMap<(u8, u8), usize> {
+        let mut result = BTreeMap::new();
+        for onu in self.onu.iter() {
+            let interface = onu.interface();
+            *result.entry((interface.slot, interface.port)).or_default() += 1;
+        }
+
+        result
+    }
+
+    // IDs ainda livres em uma porta PON
+    pub fn free_ids(&self, interface: &Interface) -> Vec<u8> {
+        let used: BTreeSet<u8> = self
+            .provisioned()
+            .iter()
+            .filter(|i| i.slot == interface.slot && i.port == interface.port)
+            .filter_map(|i| i.id)
+            .collect();
+
+        (1..=self.max_onu(interface))
+            .filter(|id| !used.contains(id))
+            .collect()
+    }
+
+    pub fn id_allocator(&self) -> OnuIdAllocator {
+        let max = self.model.chassis().max_onu_per_port();
+        OnuIdAllocator::with_existing(max, &self.provisioned())
+    }
+
+    // Interfaces de todas as linhas `onu N ...` dos blocos gpon_olt, mesmo
+    // as que não viram uma ONU (LOID, por exemplo)
+    pub fn provisioned(&self) -> Vec<Interface> {
+        let pattern = Regex::new(r"^onu (?P<id>[0-9]+) ").unwrap();
+
+        let mut result = Vec::new();
+        for block in self.configuration.0.values().flatten() {
+            block.walk(|path, command| {
+                let Some(port) = path
+                    .last()
+                    .and_then(|p| Interface::from_str(&p.text()).ok())
+                    .filter(|p| p.level == InterfaceLevel::GponOlt)
+                else {
+                    return;
+                };
+                if let Some(id) = pattern
+                    .captures(&command.text())
+                    .and_then(|c| c["id"].parse().ok())
+                {
+                    result.push(port.with_id(id));
+                }
+            });
+        }
+
+        result
+    }
+
+    // Placa instalada em um slot, de acordo com o `add-card` da configuração
+    pub fn card(&self, slot: u8) -> Option<&'static CardSpec> {
+        let pattern =
+            Regex::new(r"^add-card (rackno 1 shelfno 1 )?slotno (?P<slot>[0-9]+) (?P<name>\S+)")
+                .unwrap();
+
+        self.configuration
+            .iter_depth_first()
+            .find_map(|(_, c)| {
+                let text = c.text();
+                let card = pattern.captures(&text)?;
+                (card["slot"].parse() == Ok(slot)).then(|| self.model.chassis().card(&card["name"]))
+            })
+            .flatten()
+    }
+
+    // Quantidade máxima de ONU na porta, pela placa instalada ou pelo modelo
+    fn max_onu(&self, interface: &Interface) -> u8 {
+        match self.card(interface.slot) {
+            Some(card) => card.onu_per_port,
+            None => self.model.chassis().max_onu_per_port(),
+        }
+    }
+
+    // Verifica se a interface existe no chassi da OLT
+    pub fn validate(&self, interface: &Interface) -> Result<()> {
+        let chassis = self.model.chassis();
+        let name = format!("1/{}/{}", interface.slot, interface.port);
+
+        if !chassis.slots.iter().any(|r| r.contains(&interface.slot)) {
+            return Err(Error::Generic(format!(
+                "{name}: a {} não tem o slot {} de serviço ({})",
+                self.model,
+                interface.slot,
+                chassis.slots_description()
+            )));
+        }
+
+        let (ports, onu_per_port) = match self.card(interface.slot) {
+            Some(card) => (card.ports, card.onu_per_port),
+            None => (chassis.max_ports(), chassis.max_onu_per_port()),
+        };
+        if interface.port == 0 || interface.port > ports {
+            return Err(Error::Generic(format!(
+                "{name}: o slot {} da {} tem só {ports} portas",
+                interface.slot, self.model
+            )));
+        }
+        if let Some(id) = interface.id.filter(|id| *id > onu_per_port) {
+            return Err(Error::Generic(format!(
+                "{name}:{id}: a porta aceita no máximo {onu_per_port} ONU"
+            )));
+        }
+
+        Ok(())
+    }
+
+    // Verifica as interfaces de todas as ONU, reunindo os erros encontrados
+    pub fn validate_onus(&self, onus: &[Onu]) -> Result<()> {
+        let mut errors: Vec<String> = onus
+            .iter()
+            .filter_map(|o| self.validate(o.interface()).err())
+            .map(|e| match e {
+                Error::Generic(reason) => reason,
+                e => e.to_string(),
+            })
+            .collect();
+        let count = errors.len();
+        errors.dedup();
+
+        if !errors.is_empty() {
+            return Err(Error::Generic(format!(
+                "{count} ONU fora do chassi da {}: {}",
+                self.model,
+                errors.join("; ")
+            )));
+        }
+
+        // Duas portas antigas mapeadas para a mesma porta nova podem repetir
+        // IDs, entre si ou com as ONU que já estão na OLT
+        let provisioned = self.provisioned();
+        let mut repeated: Vec<String> = Vec::new();
+        for (index, onu) in onus.iter().enumerate() {
+            let i = onu.interface();
+            let taken = provisioned
+                .iter()
+                .chain(onus[..index].iter().map(|o| o.interface()))
+                .any(|o| o.same_onu(i));
+            let name = format!("1/{}/{}:{}", i.slot, i.port, i.id.unwrap_or_default());
+            if taken && !repeated.contains(&name) {
+                repeated.push(name);
+            }
+        }
+
+        if repeated.is_empty() {
+            return Ok(());
+        }
+
+        Err(Error::Generic(format!(
+            "{} ID de ONU repetidos na {}: {}",
+            repeated.len(),
+            self.model,
+            repeated.join(", ")
+        )))
+    }
+
+    // VLAN usadas pelos serviços das ONU
+    pub fn used_vlans(&self) -> BTreeSet<u16> {
+        self.onu
+            .iter()
+            .flat_map(|o| o.services().iter().map(|s| s.vlan.id))
+            .collect()
+    }
+
+    // Perfis definidos na configuração (`profile tcont`, `profile traffic`,
+    // `onu profile vlan`...), por tipo
+    pub fn profiles(&self) -> BTreeMap<String, BTreeSet<String>> {
+        let pattern = Regex::new(r"^(onu )?profile (?P<kind>\S+) (?P<name>\S+)").unwrap();
+
+        let mut result: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        for (_, c) in self.configuration.iter_depth_first() {
+            if let Some(p) = pattern.captures(&c.text()) {
+                result
+                    .entry(p["kind"].to_string())
+                    .or_default()
+                    .insert(p["name"].to_string());
+            }
+        }
+
+        result
+    }
+
+    // Adiciona uma ONU à OLT, devolvendo o script dela na sintaxe do modelo
+    pub fn add_onu(&mut self, onu: Onu) -> Config {
+        let script = onu.configure_script_for(self.dialect());
+        self.configuration += script.clone();
+        self.onu.push(onu);
+
+        script
+    }
+
+    // Provisiona as ONU descritas nas linhas do .csv, escolhendo IDs livres
+    // para as que não têm um, e devolve o script das ONU novas
+    pub fn provision(
+        &mut self,
+        infos: Vec<ConfigInfo>,
+        vlan: u16,
+        interface: Option<&Interface>,
+    ) -> Result<Config> {
+        let mut allocator = self.id_allocator();
+        let onus = ConfigInfo::to_onus(infos, vlan, interface, &mut allocator)?;
+        self.validate_onus(&onus)?;
+
+        let mut script = Config::default();
+        for onu in onus {
+            script += self.add_onu(onu);
+        }
+        script.sort(self.dialect());
+
+        Ok(script)
+    }
+}
+
 impl Default for InterfaceLevel {
     fn default() -> Self {
         InterfaceLevel::Other("generic".into())
@@ -52,6 +573,33 @@ impl Interface {
             ..self.clone()
         }
     }
+
+    // Interpreta uma interface `vport-1/x/y.z:n`, devolvendo a ONU e o vport
+    pub fn from_vport(value: &str) -> Result<(Interface, u8)> {
+        let pattern = Regex::new(
+            r"vport-1/(?P<card>[0-9]|1[0-9])/(?P<port>[1-9]|1[0-6])\.(?P<id>[0-9]{1,3}):(?P<vport>[0-9]+)$",
+        )
+        .unwrap();
+
+        let vport = pattern
+            .captures(value)
+            .ok_or(Error::Generic("Parse vport".to_string()))?;
+
+        let interface = Interface {
+            level: InterfaceLevel::Other("vport".into()),
+            slot: vport["card"].parse()?,
+            port: vport["port"].parse()?,
+            id: Some(vport["id"].parse()?),
+        };
+
+        Ok((interface, vport["vport"].parse()?))
+    }
+
+    // Verifica se as duas interfaces apontam para a mesma ONU,
+    // independente do nível (gpon_onu, pon-onu-mng, ...)
+    pub fn same_onu(&self, other: &Interface) -> bool {
+        self.slot == other.slot && self.port == other.port && self.id == other.id
+    }
 }
 
 impl From<&str> for InterfaceLevel {
@@ -79,7 +627,7 @@ impl FromStr for Interface {
     type Err = Error;
     fn from_str(value: &str) -> Result<Self> {
         let pattern = Regex::new(
-            r"(.* )?(?P<level>.*)[_\-]1\/(?P<card>[0-9]|1[0-9])\/(?P<port>[1-9]|1[0-6])(:(?P<id>[1-9]|[1-9][0-9]|1[0-2][09]))?$",
+            r"(.* )?(?P<level>.*)[_\-]1\/(?P<card>[0-9]|1[0-9])\/(?P<port>[1-9]|1[0-6])(:(?P<id>[1-9]|[1-9][0-9]|1[0-2][0-9]))?$",
         ).unwrap();
 
         let interface = pattern
@@ -91,8 +639,14 @@ impl FromStr for Interface {
             .map(|id| id.as_str().parse::<u8>().unwrap());
         let slot = interface["card"].parse().unwrap();
         let port = interface["port"].parse().unwrap();
+        // `pon-onu-mng gpon_onu-1/x/y:z` é o modo OMCI, não a interface da ONU
+        let level = if value.trim_start().starts_with("pon-onu-mng ") {
+            InterfaceLevel::PonOnuMng
+        } else {
+            InterfaceLevel::from(&interface["level"])
+        };
         Ok(Interface {
-            level: InterfaceLevel::from(&interface["level"]),
+            level,
             slot,
             port,
             id,
@@ -104,25 +658,298 @@ impl TryFrom<Command> for Interface {
     type Error = Error;
 
     fn try_from(value: Command) -> Result<Self> {
-        let cmd = value.raw();
-        Interface::from_str(&cmd)
+        Interface::from_str(&value.text())
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum OltModel {
     Titan(Titan),
     C3xx(C3xx),
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum C3xx {
     C320,
     C350,
     C300,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Titan {
     C610,
     C620,
     C650,
     C600,
 }
+
+impl Default for OltModel {
+    fn default() -> Self {
+        OltModel::Titan(Titan::C600)
+    }
+}
+
+impl FromStr for OltModel {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let model = match value.to_lowercase().as_str() {
+            "c300" => OltModel::C3xx(C3xx::C300),
+            "c320" => OltModel::C3xx(C3xx::C320),
+            "c350" => OltModel::C3xx(C3xx::C350),
+            "c600" => OltModel::Titan(Titan::C600),
+            "c610" => OltModel::Titan(Titan::C610),
+            "c620" => OltModel::Titan(Titan::C620),
+            "c650" => OltModel::Titan(Titan::C650),
+            _ => {
+                return Err(Error::Generic(format!(
+                    "Modelo de OLT `{value}` desconhecido"
+                )))
+            }
+        };
+
+        Ok(model)
+    }
+}
+
+impl Display for OltModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OltModel::Titan(t) => write!(f, "{t:?}"),
+            OltModel::C3xx(c) => write!(f, "{c:?}"),
+        }
+    }
+}
+
+// Tecnologia das portas de uma placa de serviço
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CardType {
+    Gpon,
+    XgsPon,
+}
+
+impl Display for CardType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CardType::Gpon => write!(f, "GPON"),
+            CardType::XgsPon => write!(f, "XGS-PON"),
+        }
+    }
+}
+
+// Placa de serviço PON suportada por um modelo
+#[derive(Clone, Copy, Debug)]
+pub struct CardSpec {
+    pub name: &'static str,
+    pub kind: CardType,
+    pub ports: u8,
+    pub onu_per_port: u8,
+}
+
+// Slots de serviço e placas PON de um modelo de OLT
+#[derive(Debug)]
+pub struct Chassis {
+    pub slots: &'static [RangeInclusive<u8>],
+    pub cards: &'static [CardSpec],
+}
+
+const GTGO: CardSpec = CardSpec {
+    name: "GTGO",
+    kind: CardType::Gpon,
+    ports: 8,
+    onu_per_port: 128,
+};
+const GTGH: CardSpec = CardSpec {
+    name: "GTGH",
+    kind: CardType::Gpon,
+    ports: 16,
+    onu_per_port: 128,
+};
+const GFCH: CardSpec = CardSpec {
+    name: "GFCH",
+    kind: CardType::Gpon,
+    ports: 16,
+    onu_per_port: 128,
+};
+const GFGL: CardSpec = CardSpec {
+    name: "GFGL",
+    kind: CardType::XgsPon,
+    ports: 16,
+    onu_per_port: 128,
+};
+
+const C300_CHASSIS: Chassis = Chassis {
+    slots: &[2..=9, 12..=19],
+    cards: &[GTGO, GTGH],
+};
+const C320_CHASSIS: Chassis = Chassis {
+    slots: &[1..=2],
+    cards: &[GTGO, GTGH],
+};
+const C350_CHASSIS: Chassis = Chassis {
+    slots: &[2..=7],
+    cards: &[GTGO, GTGH],
+};
+const C600_CHASSIS: Chassis = Chassis {
+    slots: &[1..=8, 11..=18],
+    cards: &[GFCH, GFGL],
+};
+const C610_CHASSIS: Chassis = Chassis {
+    slots: &[1..=1],
+    cards: &[GFCH, GFGL],
+};
+const C620_CHASSIS: Chassis = Chassis {
+    slots: &[1..=2],
+    cards: &[GFCH, GFGL],
+};
+const C650_CHASSIS: Chassis = Chassis {
+    slots: &[1..=8],
+    cards: &[GFCH, GFGL],
+};
+
+impl Chassis {
+    pub fn card(&self, name: &str) -> Option<&'static CardSpec> {
+        self.cards
+            .iter()
+            .find(|c| c.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn max_ports(&self) -> u8 {
+        self.cards.iter().map(|c| c.ports).max().unwrap_or_default()
+    }
+
+    pub fn max_onu_per_port(&self) -> u8 {
+        self.cards
+            .iter()
+            .map(|c| c.onu_per_port)
+            .max()
+            .unwrap_or_default()
+    }
+
+    // Slots no formato `2-9, 12-19`
+    pub fn slots_description(&self) -> String {
+        self.slots
+            .iter()
+            .map(|r| format!("{}-{}", r.start(), r.end()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+impl OltModel {
+    pub fn chassis(&self) -> &'static Chassis {
+        match self {
+            OltModel::C3xx(C3xx::C300) => &C300_CHASSIS,
+            OltModel::C3xx(C3xx::C320) => &C320_CHASSIS,
+            OltModel::C3xx(C3xx::C350) => &C350_CHASSIS,
+            OltModel::Titan(Titan::C600) => &C600_CHASSIS,
+            OltModel::Titan(Titan::C610) => &C610_CHASSIS,
+            OltModel::Titan(Titan::C620) => &C620_CHASSIS,
+            OltModel::Titan(Titan::C650) => &C650_CHASSIS,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::onu::{OnuService, Vlan};
+
+    fn olt(model: OltModel, backup: &str) -> Olt {
+        Olt::new(model, Config::from_str(backup).unwrap())
+    }
+
+    // ONU com um serviço na VLAN dada
+    fn onu(interface: &str, vlan: u16) -> Onu {
+        Onu::new(
+            Interface::from_str(interface).unwrap(),
+            "ZTE-F601",
+            Some("ZTEG00000001"),
+            vec![OnuService::new(Vlan::new(vlan))],
+        )
+    }
+
+    #[test]
+    fn allocator_fills_the_port() {
+        let port = Interface::from_str("gpon_olt-1/1/1").unwrap();
+        let mut allocator = OnuIdAllocator::with_existing(3, &[port.with_id(2)]);
+
+        assert!(allocator.reserve(&port.with_id(2)).is_err());
+        assert!(allocator.reserve(&port.with_id(4)).is_err());
+        assert_eq!(allocator.allocate(&port).unwrap().id, Some(1));
+        assert_eq!(allocator.allocate(&port).unwrap().id, Some(3));
+        assert!(allocator.allocate(&port).is_err());
+
+        // As outras portas continuam livres
+        let other = Interface::from_str("gpon_olt-1/1/2").unwrap();
+        assert_eq!(allocator.allocate(&other).unwrap().id, Some(1));
+    }
+
+    #[test]
+    fn port_map_rejects_unmapped_ports() {
+        let map = PortMap::from_str("[ports]\n\"gpon-olt_1/2/1\" = \"gpon_olt-1/1/1\"\n").unwrap();
+        let mut onus = vec![onu("gpon-onu_1/2/1:1", 100), onu("gpon-onu_1/2/2:1", 100)];
+
+        assert!(map.apply(&mut onus).is_err());
+        // Nenhuma ONU é movida quando falta alguma porta
+        assert_eq!(onus[0].interface().slot, 2);
+    }
+
+    #[test]
+    fn port_map_merging_ports_repeats_ids() {
+        let map = PortMap::from_str(
+            "[ports]\n\"gpon-olt_1/2/1\" = \"gpon_olt-1/1/1\"\n\"gpon-olt_1/2/2\" = \"gpon_olt-1/1/1\"\n",
+        )
+        .unwrap();
+        let mut onus = vec![
+            onu("gpon-onu_1/2/1:1", 100),
+            onu("gpon-onu_1/2/1:2", 100),
+            onu("gpon-onu_1/2/2:1", 100),
+        ];
+        map.apply(&mut onus).unwrap();
+        assert!(onus
+            .iter()
+            .all(|o| (o.interface().slot, o.interface().port) == (1, 1)));
+
+        let olt = Olt::from(OltModel::Titan(Titan::C600));
+        let error = olt.validate_onus(&onus).unwrap_err().to_string();
+        assert!(error.contains("1/1/1:1"), "{error}");
+        assert!(!error.contains("1/1/1:2"), "{error}");
+    }
+
+    #[test]
+    fn vlan_map_port_override() {
+        let map =
+            VlanMap::from_str("[vlans]\n100 = 200\n\n[ports.\"gpon-olt_1/2/1\"]\n100 = 300\n")
+                .unwrap();
+        let mut onus = vec![onu("gpon-onu_1/2/1:1", 100), onu("gpon-onu_1/2/2:1", 100)];
+
+        let summary = map.apply(&mut onus);
+        assert_eq!(onus[0].services()[0].vlan.id, 300);
+        assert_eq!(onus[1].services()[0].vlan.id, 200);
+        assert_eq!(summary[&(100, 300)], 1);
+        assert_eq!(summary[&(100, 200)], 1);
+    }
+
+    #[test]
+    fn unparsed_onu_lines_reserve_their_id() {
+        let olt = olt(
+            OltModel::Titan(Titan::C600),
+            "!<xpon>\ninterface gpon_olt-1/2/1\n  onu 1 type ZTE-F601 loid ABC123\n  onu 2 type ZTE-F601 sn ZTEG00000002\n$\n!</xpon>\n",
+        );
+        assert_eq!(olt.onus().len(), 1);
+
+        let port = Interface::from_str("gpon_olt-1/2/1").unwrap();
+        let mut allocator = olt.id_allocator();
+        assert_eq!(allocator.allocate(&port).unwrap().id, Some(3));
+        assert_eq!(olt.free_ids(&port).first(), Some(&3));
+        assert!(olt
+            .validate_onus(&[Onu::new(
+                port.with_id(1),
+                "ZTE-F601",
+                Some("ZTEG00000009"),
+                Vec::new()
+            )])
+            .is_err());
+    }
+}
@@ -1,5 +1,6 @@
-use std::{collections::HashMap, rc::Rc};
+use std::{collections::HashMap, rc::Rc, str::FromStr};
 
+use indexmap::IndexMap;
 use regex::Regex;
 
 use crate::{
@@ -9,64 +10,392 @@ use crate::{
 
 use super::{
     command::{
-        interface::InterfaceOnu,
+        interface::{InterfaceOnu, InterfaceVport},
         omci::{IngressType, Protocol, WanMode},
-        CmdArg0, CommandBuilder,
+        CmdArg0, CmdArg1, CommandBuilder,
     },
     configuration::{Config, ConfigField, NestedCommand},
-    olt::Interface,
+    dialect::Dialect,
+    olt::{Interface, InterfaceLevel},
 };
 
+// ONU ainda não configurada, vista pelo `show gpon onu uncfg`
+#[derive(Clone, Debug, PartialEq)]
+pub struct UncfgOnu {
+    pub interface: Interface,
+    pub sn: String,
+    pub model: Option<String>,
+    pub password: Option<String>,
+}
+
+impl UncfgOnu {
+    // Interpreta a tabela do `show gpon onu uncfg` (C300) ou do
+    // `show pon onu uncfg` (Titan). As colunas variam entre versões, então
+    // elas são localizadas pelo cabeçalho, a linha logo acima dos traços:
+    // a primeira é a interface, e as demais são achadas pelo título.
+    pub fn parse_table(table: &str) -> Vec<UncfgOnu> {
+        let separator = Regex::new(r"^\s*-+(\s+-+)*\s*$").unwrap();
+        let word = Regex::new(r"\S+").unwrap();
+
+        let lines: Vec<&str> = table.lines().collect();
+        let Some(dashes) = lines
+            .iter()
+            .position(|l| separator.is_match(l))
+            .filter(|p| *p > 0)
+        else {
+            return Vec::new();
+        };
+
+        let titles: Vec<(usize, String)> = word
+            .find_iter(lines[dashes - 1])
+            .map(|m| (m.start(), m.as_str().to_lowercase()))
+            .collect();
+        // Na Titan cada coluna tem o seu grupo de traços, que marca a largura
+        // dela melhor que o título
+        let groups: Vec<usize> = word.find_iter(lines[dashes]).map(|m| m.start()).collect();
+        let starts: Vec<usize> = if groups.len() == titles.len() {
+            groups
+        } else {
+            titles.iter().map(|(start, _)| *start).collect()
+        };
+        let column = |names: &[&str]| titles.iter().position(|(_, t)| names.contains(&t.as_str()));
+        let Some(sn) = column(&["sn"]) else {
+            return Vec::new();
+        };
+        let model = column(&["model", "type"]);
+        let password = column(&["pw", "password"]);
+
+        let mut result = Vec::new();
+        for line in &lines[dashes + 1..] {
+            let cell = |index: usize| {
+                let start = starts[index].min(line.len());
+                let end = starts
+                    .get(index + 1)
+                    .map_or(line.len(), |e| (*e).min(line.len()));
+                line.get(start..end)
+                    .map(str::trim)
+                    .filter(|c| !c.is_empty() && !c.eq_ignore_ascii_case("N/A"))
+            };
+
+            let Some(interface) = cell(0).and_then(|i| Interface::from_str(i).ok()) else {
+                continue;
+            };
+            let Some(sn) = cell(sn) else {
+                continue;
+            };
+
+            result.push(UncfgOnu {
+                interface: Interface {
+                    level: InterfaceLevel::GponOlt,
+                    id: None,
+                    ..interface
+                },
+                sn: sn.to_string(),
+                model: model.and_then(cell).map(str::to_string),
+                password: password.and_then(cell).map(str::to_string),
+            });
+        }
+
+        result
+    }
+
+    // Palpite do tipo de ONU a partir do modelo informado pela OLT,
+    // removendo a versão de hardware (`F670LV9.0` vira `ZTE-F670L`)
+    pub fn type_guess(&self) -> Option<String> {
+        let version = Regex::new(r"\s*V[0-9]+(\.[0-9]+)*$").unwrap();
+        let model = self.model.as_deref()?;
+        // Algumas versões separam o fabricante e a versão com espaços
+        let model = version
+            .replace(model, "")
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join("-");
+        if model.starts_with("ZTE-") || !self.sn.starts_with("ZTEG") {
+            Some(model.to_string())
+        } else {
+            Some(format!("ZTE-{model}"))
+        }
+    }
+}
+
 pub struct Onu {
     interface: Interface,
     model: Box<str>,
-    sn: Box<str>,
+    // Ausente nas ONU autenticadas só por senha
+    sn: Option<Box<str>>,
+    password: Option<Box<str>>,
+    settings: OnuSettings,
     services: Rc<[OnuService]>,
 }
 
+// Configurações feitas dentro de `interface gpon_onu-1/x/y:z`
+#[derive(Clone, Debug, Default)]
+pub struct OnuSettings {
+    pub name: Option<Box<str>>,
+    pub description: Option<Box<str>>,
+    pub sn_bind: Option<Box<str>>,
+    pub tconts: Vec<Tcont>,
+    pub gemports: Vec<Gemport>,
+    pub vport_mode: Option<Box<str>>,
+    pub vports: Vec<Vport>,
+    pub vport_maps: Vec<VportMap>,
+    // Comandos que não são interpretados, mantidos como estão
+    pub other: Vec<Command>,
+    // Comandos das interfaces vport que não são interpretados, com o vport
+    pub vport_other: Vec<(u8, Command)>,
+    // Comandos do pon-onu-mng que não são interpretados. Só as ONU novas,
+    // sem pon-onu-mng (`None`), ganham o security-mgmt de cada WAN.
+    pub mng_other: Option<Vec<Command>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tcont {
+    pub id: u8,
+    pub name: Option<Box<str>>,
+    pub profile: Box<str>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gemport {
+    pub id: u8,
+    pub name: Option<Box<str>>,
+    pub tcont: u8,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Vport {
+    pub id: u8,
+    pub map_type: Box<str>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VportMap {
+    pub vport: u8,
+    pub gemport: u8,
+    pub vlan: u16,
+}
+
+impl OnuSettings {
+    // Interpreta um comando de dentro da interface gpon_onu
+    pub fn parse_command(&mut self, command: &Command) {
+        let text = |value: &Rc<str>| Some(Box::from(value.as_ref()));
+
+        match command {
+            Command::Name(name) => self.name = text(name),
+            Command::Description(description) => self.description = text(description),
+            Command::SnBind(mode) => self.sn_bind = text(mode),
+            Command::VportMode(mode) => self.vport_mode = text(mode),
+            Command::Tcont { id, name, profile } => self.tconts.push(Tcont {
+                id: *id,
+                name: name.as_deref().map(Box::from),
+                profile: profile.as_ref().into(),
+            }),
+            Command::Gemport { id, name, tcont } => self.gemports.push(Gemport {
+                id: *id,
+                name: name.as_deref().map(Box::from),
+                tcont: *tcont,
+            }),
+            Command::Vport { id, map_type } => self.vports.push(Vport {
+                id: *id,
+                map_type: map_type.as_ref().into(),
+            }),
+            Command::VportMap {
+                vport,
+                gemport,
+                vlan,
+            } => self.vport_maps.push(VportMap {
+                vport: *vport,
+                gemport: *gemport,
+                vlan: *vlan,
+            }),
+            _ => self.other.push(command.clone()),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct OnuService {
     pub vlan: Vlan,
+    // Gemport do `service N gemport G` de uma configuração existente
+    pub gemport: Option<u8>,
     pub upload: Option<Box<str>>,
     pub download: Option<Box<str>>,
+    pub service_port: Option<ServicePort>,
+}
+
+// `service-port` de um serviço, tanto no estilo C300
+// (`service-port 1 vport 1 user-vlan ...` dentro da interface gpon_onu)
+// quanto no C600 (`service-port 1 user-vlan ...` dentro da interface vport)
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServicePort {
+    pub id: u8,
+    pub vport: u8,
+    pub user_vlan: u16,
+    pub user_etype: Option<Box<str>>,
+    pub vlan: u16,
+    pub svlan: Option<u16>,
+    pub ingress: Option<Box<str>>,
+    pub egress: Option<Box<str>>,
+    // Parâmetros que não são interpretados, mantidos como estão
+    pub extra: Option<Box<str>>,
+}
+
+impl ServicePort {
+    pub fn new(id: u8, vport: u8, user_vlan: u16, vlan: u16) -> ServicePort {
+        ServicePort {
+            id,
+            vport,
+            user_vlan,
+            user_etype: None,
+            vlan,
+            svlan: None,
+            ingress: None,
+            egress: None,
+            extra: None,
+        }
+    }
+
+    // Interpreta um `service-port`. No estilo C600 o vport não faz parte do
+    // comando, e sim da interface, por isso é passado separadamente.
+    // Sem o `vlan` logo após o `user-vlan` o comando não é interpretado, para
+    // que seja mantido como está.
+    pub fn parse(command: &Command, vport: Option<u8>) -> Result<ServicePort> {
+        let Command::ServicePort {
+            id,
+            vport: command_vport,
+            user_vlan,
+            user_etype,
+            vlan: Some(vlan),
+            svlan,
+            ingress,
+            egress,
+            extra,
+        } = command
+        else {
+            return Err(Error::Generic("Parse service-port".to_string()));
+        };
+
+        Ok(ServicePort {
+            id: *id,
+            vport: command_vport.or(vport).unwrap_or(1),
+            user_vlan: *user_vlan,
+            user_etype: user_etype.as_deref().map(Box::from),
+            vlan: *vlan,
+            svlan: *svlan,
+            ingress: ingress.as_deref().map(Box::from),
+            egress: egress.as_deref().map(Box::from),
+            extra: extra.as_deref().map(Box::from),
+        })
+    }
+
+    // Gera o comando a partir do início `service-port N [vport N]`
+    pub fn command(&self, builder: CommandBuilder<InterfaceVport, CmdArg1>) -> Command {
+        let mut user_vlan = builder.user_vlan(self.user_vlan);
+        if let Some(etype) = &self.user_etype {
+            user_vlan = user_vlan.user_etype(etype.clone());
+        }
+        let mut service_port = user_vlan.vlan(self.vlan);
+        if let Some(svlan) = self.svlan {
+            service_port = service_port.svlan(svlan);
+        }
+        if let Some(ingress) = &self.ingress {
+            service_port = service_port.ingress(ingress.clone());
+        }
+        if let Some(egress) = &self.egress {
+            service_port = service_port.egress(egress.clone());
+        }
+
+        if let Some(extra) = &self.extra {
+            service_port = service_port.extra(extra.clone());
+        }
+
+        service_port.run()
+    }
 }
 
 #[derive(Clone)]
 pub struct Vlan {
     pub id: u16,
     pub service: Option<WanMode>,
+    // Nome do vlan-profile usado na WAN, quando não é o próprio número da VLAN
+    pub profile: Option<Box<str>>,
+    // ip-profile da WAN, quando existe
+    pub ip_profile: Option<Box<str>>,
 }
 
 impl TryFrom<&Command> for Vlan {
     type Error = Error;
 
     fn try_from(value: &Command) -> Result<Self> {
-        let wan_pattern = Regex::new(
-            r"^wan-ip [0-9] mode (?P<service>(pppoe username (?P<username>.*) password (?P<password>.*))|(dhcp)) vlan-profile (?P<vlan>.*) host [0-9]",
-        ).unwrap();
-
-        if let Some(info) = wan_pattern.captures(value.as_str()) {
-            let id = info["vlan"].parse::<u16>().unwrap();
-            let mut new_vlan = Vlan::new(id);
-
-            if info["service"].is_empty() {
-                if info["username"].is_empty() {
-                    new_vlan.dhcp()
-                } else {
-                    new_vlan.pppoe(&info["username"], &info["password"])
-                }
-            }
-
-            Ok(new_vlan)
-        } else {
-            Err(Error::Generic("Parse vlan".to_string()))
-        }
+        Vlan::parse_wan(value).map(|(vlan, _)| vlan)
     }
 }
 
 impl Vlan {
     pub fn new(id: u16) -> Vlan {
-        Vlan { id, service: None }
+        Vlan {
+            id,
+            service: None,
+            profile: None,
+            ip_profile: None,
+        }
+    }
+
+    // Interpreta uma linha de WAN do modo pon-onu-mng, devolvendo também o
+    // host a que ela pertence. Aceita `wan-ip ipv4 ...` (C600) e `wan-ip N ...`
+    // (C300) nos modos pppoe, dhcp e static, e `vlan port eth_0/N mode tag`
+    // para ONU em bridge.
+    pub fn parse_wan(value: &Command) -> Result<(Vlan, u8)> {
+        let (mode, profile, ip_profile, host) = match value {
+            Command::VlanPort { port, vlan } => {
+                let mut new_vlan = Vlan::new(*vlan);
+                new_vlan.bridge(*port);
+                return Ok((new_vlan, 1));
+            }
+            Command::WanIp {
+                mode,
+                ip_profile,
+                vlan_profile: Some(profile),
+                host,
+                ..
+            } if !matches!(mode, WanMode::Bridge { .. }) => (mode, profile, ip_profile, host),
+            _ => return Err(Error::Generic("Parse vlan".to_string())),
+        };
+
+        // O vlan-profile pode ser só o número da VLAN ou um nome que o contém
+        let id = Regex::new(r"[0-9]+")
+            .unwrap()
+            .find(profile)
+            .and_then(|n| n.as_str().parse::<u16>().ok())
+            .unwrap_or_default();
+        let mut new_vlan = Vlan::new(id);
+        if **profile != *id.to_string() {
+            new_vlan.profile = Some(profile.as_ref().into());
+        }
+        new_vlan.service = Some(mode.clone());
+        new_vlan.ip_profile = ip_profile.as_deref().map(Box::from);
+
+        Ok((new_vlan, host.unwrap_or(1)))
+    }
+
+    // Troca a VLAN, ajustando também o número no nome do vlan-profile, o
+    // mesmo que o `parse_wan` usou como VLAN
+    pub fn translate(&mut self, id: u16) {
+        if let Some(profile) = &self.profile {
+            let number = Regex::new(r"[0-9]+").unwrap().find(profile);
+            if let Some(n) = number.filter(|n| n.as_str().parse() == Ok(self.id)) {
+                let renamed = format!("{}{id}{}", &profile[..n.start()], &profile[n.end()..]);
+                self.profile = Some(renamed.into());
+            }
+        }
+        self.id = id;
+    }
+
+    // Nome do vlan-profile usado na WAN
+    pub fn profile_name(&self) -> String {
+        self.profile
+            .as_deref()
+            .map_or(self.id.to_string(), String::from)
     }
 
     pub fn pppoe(&mut self, username: impl Into<String>, password: impl Into<String>) {
@@ -79,25 +408,68 @@ impl Vlan {
     pub fn dhcp(&mut self) {
         self.service = Some(WanMode::Dhcp);
     }
+
+    pub fn static_ip(
+        &mut self,
+        ip: impl Into<String>,
+        mask: impl Into<String>,
+        gateway: impl Into<String>,
+    ) {
+        self.service = Some(WanMode::Static {
+            ip: ip.into(),
+            mask: mask.into(),
+            gateway: gateway.into(),
+        });
+    }
+
+    pub fn bridge(&mut self, port: u8) {
+        self.service = Some(WanMode::Bridge { port });
+    }
 }
 
 impl OnuService {
     pub fn new(vlan: Vlan) -> OnuService {
         OnuService {
             vlan,
+            gemport: None,
             upload: None,
             download: None,
+            service_port: None,
         }
     }
+
+    pub fn with_bandwidth(mut self, upload: Option<&str>, download: Option<&str>) -> OnuService {
+        self.upload = upload.map(Box::from);
+        self.download = download.map(Box::from);
+        self
+    }
+
+    // Os perfis de tráfego do service-port definem a banda do serviço
+    pub fn set_service_port(&mut self, service_port: ServicePort) {
+        if self.upload.is_none() {
+            self.upload = service_port.ingress.clone();
+        }
+        if self.download.is_none() {
+            self.download = service_port.egress.clone();
+        }
+        self.service_port = Some(service_port);
+    }
 }
 
 impl Onu {
-    pub fn new(interface: Interface, model: &str, sn: &str, services: Vec<OnuService>) -> Onu {
+    pub fn new(
+        interface: Interface,
+        model: &str,
+        sn: Option<&str>,
+        services: Vec<OnuService>,
+    ) -> Onu {
         Onu {
             interface,
             services: Rc::from(services),
             model: Box::from(model),
-            sn: Box::from(sn),
+            sn: sn.map(Box::from),
+            password: None,
+            settings: OnuSettings::default(),
         }
     }
 
@@ -105,103 +477,334 @@ impl Onu {
         &self.interface
     }
 
+    pub fn settings_mut(&mut self) -> &mut OnuSettings {
+        &mut self.settings
+    }
+
+    // Move a ONU para outra porta PON, mantendo o ID
+    pub fn move_to(&mut self, slot: u8, port: u8) {
+        self.interface.slot = slot;
+        self.interface.port = port;
+    }
+
+    pub fn set_password(&mut self, password: Option<&str>) {
+        self.password = password.map(Box::from);
+    }
+
+    // Troca as VLAN dos serviços, vport-map e service-port de acordo com o
+    // mapa, devolvendo as trocas feitas
+    pub fn translate_vlans(&mut self, map: &HashMap<u16, u16>) -> Vec<(u16, u16)> {
+        let mut changes = Vec::new();
+        let mut services = self.services.to_vec();
+        for service in services.iter_mut() {
+            let Some(&new) = map.get(&service.vlan.id) else {
+                continue;
+            };
+            if !changes.contains(&(service.vlan.id, new)) {
+                changes.push((service.vlan.id, new));
+            }
+            service.vlan.translate(new);
+        }
+
+        for sp in services.iter_mut().filter_map(|s| s.service_port.as_mut()) {
+            if let Some(&new) = map.get(&sp.user_vlan) {
+                sp.user_vlan = new;
+            }
+            if let Some(&new) = map.get(&sp.vlan) {
+                sp.vlan = new;
+            }
+        }
+        for vport_map in self.settings.vport_maps.iter_mut() {
+            if let Some(&new) = map.get(&vport_map.vlan) {
+                vport_map.vlan = new;
+            }
+        }
+
+        self.services = services.into();
+        changes
+    }
+
     pub fn set_service(&mut self, services: Rc<[OnuService]>) {
         self.services = services;
     }
 
-    pub fn configure_script(&self) -> Config {
-        // Definição das variáveis
-        let tcont = 1;
-        let speed_profile = "1G";
-        let gemport = 1;
+    pub fn services(&self) -> &[OnuService] {
+        &self.services
+    }
+
+    // Associa um service-port ao serviço com a mesma VLAN de usuário,
+    // criando um serviço novo caso nenhum corresponda.
+    pub fn attach_service_port(&mut self, service_port: ServicePort) {
+        let mut services = self.services.to_vec();
+        match services
+            .iter_mut()
+            .find(|s| s.vlan.id == service_port.user_vlan && s.service_port.is_none())
+        {
+            Some(service) => service.set_service_port(service_port),
+            None => {
+                let mut service = OnuService::new(Vlan::new(service_port.user_vlan));
+                service.set_service_port(service_port);
+                services.push(service);
+            }
+        }
+
+        self.services = services.into();
+    }
+
+    // Gera o script da ONU na sintaxe de um modelo de OLT
+    pub fn configure_script_for(&self, dialect: &dyn Dialect) -> Config {
+        // Sem tcont e gemport na configuração, cada serviço ganha os seus,
+        // numerados a partir de 1, com o perfil de upload do serviço
+        let generated = self.services.len().max(1) as u8;
+        let speed_profile = |id: u8| {
+            self.services
+                .get(usize::from(id) - 1)
+                .and_then(|s| s.upload.as_deref())
+                .unwrap_or("1G")
+        };
+        // Gemport usado por cada serviço
+        let service_gemport = |index: usize, service: &OnuService| {
+            service
+                .gemport
+                .or(self.settings.gemports.get(index).map(|g| g.id))
+                .or(self.settings.gemports.first().map(|g| g.id))
+                .unwrap_or(index as u8 + 1)
+        };
 
         // Cria um mapa vazio onde serão armazenados os comandos.
-        let mut script = HashMap::new();
-        let interface_field = ConfigField::from("if-intf");
+        let mut script = IndexMap::new();
         let xpon_field = ConfigField::from("xpon");
         let msan_field = ConfigField::from("MSAN");
 
-        script.insert(interface_field.clone(), Vec::new());
         script.insert(xpon_field.clone(), Vec::new());
-        script.insert(msan_field.clone(), Vec::new());
 
         // Entra no modo conf t
         let enter_configure = Command::builder();
 
         // Comando para entrar na interface pon onde está a ONU.
-        let enter_interface_olt = enter_configure.interface().gpon_olt(self.interface());
+        let enter_interface_olt = enter_configure
+            .interface()
+            .gpon_olt_in(dialect, self.interface());
         let mut interface_olt = NestedCommand::from(enter_interface_olt.command.clone());
 
         // Comando para adicionar uma ONU não configurada.
         let add_onu = enter_interface_olt
             .onu(self.interface.id.unwrap())
-            .r#type(self.model.clone())
-            .sn(self.sn.clone())
-            .run();
+            .r#type(self.model.clone());
+        let add_onu = match (&self.sn, &self.password) {
+            (Some(sn), Some(p)) => add_onu.sn(sn.clone()).pw(p.clone()).run(),
+            (Some(sn), None) => add_onu.sn(sn.clone()).run(),
+            (None, Some(p)) => add_onu.pw(p.clone()).run(),
+            (None, None) => add_onu.command,
+        };
         interface_olt.nest(add_onu.into());
-        script
-            .get_mut(&interface_field)
-            .unwrap()
-            .push(interface_olt);
+        script.get_mut(&xpon_field).unwrap().push(interface_olt);
 
         // Comando para entrar na interface pon da ONU.
-        let enter_onu_interface: CommandBuilder<InterfaceOnu, CmdArg0> =
-            Command::builder().interface().gpon_onu(self.interface());
+        let enter_onu_interface: CommandBuilder<InterfaceOnu, CmdArg0> = Command::builder()
+            .interface()
+            .gpon_onu_in(dialect, self.interface());
         let mut interface_onu = NestedCommand::from(enter_onu_interface.command.clone());
-        script
-            .get_mut(&interface_field)
-            .unwrap()
-            .push(interface_onu.clone());
-
-        // Configura o tcont com um perfil de velocidade padrão.
-        let tcont_profile = enter_onu_interface
-            .clone()
-            .tcont(tcont)
-            .profile(speed_profile);
-        interface_onu.nest(tcont_profile.into());
-
-        // Cria o gemport
-        let gemport_tcont = enter_onu_interface.gemport(gemport).tcont(tcont).run();
-        interface_onu.nest(gemport_tcont.into());
-        interface_onu.nest(Command::from("vport-mode manual").into());
-        interface_onu.nest(Command::from("vport 1 map-type vlan").into());
-
-        for service in self.services.iter().enumerate() {
+
+        let settings = &self.settings;
+        if let Some(name) = &settings.name {
+            interface_onu.nest(enter_onu_interface.clone().name(name.clone()).into());
+        }
+        if let Some(description) = &settings.description {
             interface_onu.nest(
-                Command::from(
-                    format!("vport-map 1 {} vlan {}", service.0, service.1.vlan.id,).as_str(),
-                )
-                .into(),
+                enter_onu_interface
+                    .clone()
+                    .description(description.clone())
+                    .into(),
             );
         }
+        if let Some(sn_bind) = &settings.sn_bind {
+            interface_onu.nest(enter_onu_interface.clone().sn_bind(sn_bind.clone()).into());
+        }
 
-        script.get_mut(&xpon_field).unwrap().push(interface_onu);
+        // Configura os tcont, ou um tcont por serviço
+        let default_tconts: Vec<Tcont> = (1..=generated)
+            .map(|id| Tcont {
+                id,
+                name: None,
+                profile: speed_profile(id).into(),
+            })
+            .collect();
+        let tconts = if settings.tconts.is_empty() {
+            &default_tconts[..]
+        } else {
+            &settings.tconts[..]
+        };
+        for t in tconts {
+            let tcont_profile = enter_onu_interface.clone().tcont(t.id);
+            let tcont_profile = match &t.name {
+                Some(n) => tcont_profile.name(n.clone()),
+                None => tcont_profile,
+            };
+            interface_onu.nest(tcont_profile.profile(t.profile.clone()).into());
+        }
+
+        // Cria os gemport, cada um no tcont de mesmo número
+        let default_gemports: Vec<Gemport> = (1..=generated)
+            .map(|id| Gemport {
+                id,
+                name: None,
+                tcont: id,
+            })
+            .collect();
+        let gemports = if settings.gemports.is_empty() {
+            &default_gemports[..]
+        } else {
+            &settings.gemports[..]
+        };
+        for g in gemports {
+            let gemport_tcont = enter_onu_interface.clone().gemport(g.id);
+            let gemport_tcont = match &g.name {
+                Some(n) => gemport_tcont.name(n.clone()),
+                None => gemport_tcont,
+            };
+            interface_onu.nest(gemport_tcont.tcont(g.tcont).run().into());
+        }
+
+        // vport-mode, vport e vport-map só existem na linha Titan
+        if dialect.has_vport() {
+            let vport_mode = settings.vport_mode.as_deref().unwrap_or("manual");
+            interface_onu.nest(enter_onu_interface.clone().vport_mode(vport_mode).into());
+
+            if settings.vports.is_empty() {
+                for id in 1..=generated {
+                    interface_onu.nest(
+                        enter_onu_interface
+                            .clone()
+                            .vport(id)
+                            .map_type("vlan")
+                            .into(),
+                    );
+                }
+            }
+            for v in settings.vports.iter() {
+                interface_onu.nest(
+                    enter_onu_interface
+                        .clone()
+                        .vport(v.id)
+                        .map_type(v.map_type.clone())
+                        .into(),
+                );
+            }
+
+            // Cada serviço no vport de mesmo número, com o gemport dele
+            if settings.vport_maps.is_empty() {
+                for (index, service) in self.services.iter().enumerate() {
+                    interface_onu.nest(
+                        enter_onu_interface
+                            .clone()
+                            .vport_map(index as u8 + 1, service_gemport(index, service))
+                            .vlan(service.vlan.id)
+                            .into(),
+                    );
+                }
+            }
+            for m in settings.vport_maps.iter() {
+                interface_onu.nest(
+                    enter_onu_interface
+                        .clone()
+                        .vport_map(m.vport, m.gemport)
+                        .vlan(m.vlan)
+                        .into(),
+                );
+            }
+        }
+
+        for c in settings.other.iter() {
+            interface_onu.nest(c.clone().into());
+        }
 
         // Cria os serviços
         for (index, service) in self.services.iter().enumerate() {
-            let vport_id = index as u8 + 1;
+            let service_id = index as u8 + 1;
+            // Na C300 o vport do service-port é o gemport do serviço
+            let default_vport = if dialect.service_port_in_onu() {
+                service_gemport(index, service)
+            } else {
+                service_id
+            };
+
+            // Os perfis de banda do serviço têm prioridade sobre os já existentes
+            let service_port = match &service.service_port {
+                Some(sp) => ServicePort {
+                    ingress: service.upload.clone().or(sp.ingress.clone()),
+                    egress: service.download.clone().or(sp.egress.clone()),
+                    ..sp.clone()
+                },
+                None => ServicePort {
+                    ingress: service.upload.clone(),
+                    egress: service.download.clone(),
+                    ..ServicePort::new(service_id, default_vport, service.vlan.id, service.vlan.id)
+                },
+            };
+
+            if dialect.service_port_in_onu() {
+                // Estilo C300, dentro da interface da ONU
+                let builder = enter_onu_interface
+                    .clone()
+                    .service_port(service_port.id)
+                    .vport(service_port.vport);
+                interface_onu.nest(service_port.command(builder).into());
+                continue;
+            }
+
             // Entra na interface vport para configurar o serviço
             let enter_vport = Command::builder()
                 .interface()
-                .vport(self.interface(), vport_id);
+                .vport(self.interface(), service_port.vport);
             let mut interface_vport = NestedCommand::from(enter_vport.command.clone());
+
+            let vport = service_port.vport;
+            let service_port = service_port.command(enter_vport.service_port(service_port.id));
+            interface_vport.nest(service_port.into());
+            for (_, c) in settings.vport_other.iter().filter(|(v, _)| *v == vport) {
+                interface_vport.nest(c.clone().into());
+            }
+
             script
-                .get_mut(&interface_field)
-                .unwrap()
-                .push(interface_vport.clone());
+                .entry(msan_field.clone())
+                .or_default()
+                .push(interface_vport);
+        }
 
-            let servive_port = enter_vport
-                .service_port(vport_id)
-                .user_vlan(service.vlan.id)
-                .vlan(service.vlan.id)
-                .run();
-            interface_vport.nest(servive_port.into());
+        // Comandos de vport sem nenhum service-port gerado no mesmo vport
+        let mut vports: Vec<u8> = settings.vport_other.iter().map(|(v, _)| *v).collect();
+        vports.sort();
+        vports.dedup();
+        for vport in vports {
+            let lines = settings
+                .vport_other
+                .iter()
+                .filter(|(v, _)| *v == vport)
+                .map(|(_, c)| NestedCommand::from(c.clone()));
+
+            // Na C300 não há interface vport, os comandos vão para a ONU
+            if dialect.service_port_in_onu() {
+                lines.for_each(|c| interface_onu.nest(c));
+                continue;
+            }
 
-            script.get_mut(&msan_field).unwrap().push(interface_vport);
+            let enter_vport = Command::builder()
+                .interface()
+                .vport(self.interface(), vport);
+            let msan = script.entry(msan_field.clone()).or_default();
+            if msan.iter().any(|c| c.command == enter_vport.command) {
+                continue;
+            }
+            let mut interface_vport = NestedCommand::from(enter_vport.command);
+            lines.for_each(|c| interface_vport.nest(c));
+            msan.push(interface_vport);
         }
 
+        script.get_mut(&xpon_field).unwrap().push(interface_onu);
+
         // Entra no modo de configuração OMCI
-        let enter_pon_mng = Command::builder().pon_onu_mng(self.interface());
+        let enter_pon_mng = Command::builder().pon_onu_mng_in(dialect, self.interface());
         let mut pon_onu_mng = NestedCommand::from(enter_pon_mng.command.clone());
 
         for (index, service) in self.services.iter().enumerate() {
@@ -210,33 +813,110 @@ impl Onu {
             let service_gemport = enter_pon_mng
                 .clone()
                 .service(service_id)
-                .gemport(gemport)
+                .gemport(service_gemport(index, service))
                 .vlan(service.vlan.id);
             pon_onu_mng.nest(service_gemport.into());
 
-            if let Some(p) = service.vlan.service.clone() {
-                // Cria a WAN em pppoe
-                let wan_ip = enter_pon_mng
-                    .clone()
-                    .wan_ip()
-                    .mode(p)
-                    .vlan_profile(service.vlan.id)
+            if let Some(WanMode::Bridge { port }) = service.vlan.service {
+                // Entrega a VLAN marcada na porta ethernet
+                let vlan_port = enter_pon_mng.clone().vlan_port(port).tag(service.vlan.id);
+                pon_onu_mng.nest(vlan_port.into());
+            } else if let Some(p) = service.vlan.service.clone() {
+                // Cria a WAN
+                let wan_ip = enter_pon_mng.clone().wan_ip_in(dialect, service_id).mode(p);
+                let wan_ip = match &service.vlan.ip_profile {
+                    Some(ip_profile) => wan_ip.ip_profile(ip_profile.clone()),
+                    None => wan_ip,
+                };
+                let wan_ip = wan_ip
+                    .vlan_profile(service.vlan.profile_name())
                     .host(service_id);
                 pon_onu_mng.nest(wan_ip.into());
 
                 // Cria a regra para o acesso web
-                let security_mgmt = enter_pon_mng
-                    .clone()
-                    .security_mgmt(service_id)
-                    .state(true)
-                    .mode(true)
-                    .ingress_type(IngressType::Iphost(1))
-                    .protocol(Protocol::Web);
-                pon_onu_mng.nest(security_mgmt.into());
+                if settings.mng_other.is_none() {
+                    let security_mgmt = enter_pon_mng
+                        .clone()
+                        .security_mgmt(service_id)
+                        .state(true)
+                        .mode(true)
+                        .ingress_type(IngressType::Iphost(service_id))
+                        .protocol(Protocol::Web);
+                    pon_onu_mng.nest(security_mgmt.into());
+                }
             }
         }
+        for command in settings.mng_other.iter().flatten() {
+            pon_onu_mng.nest(command.clone().into());
+        }
 
         script.get_mut(&xpon_field).unwrap().push(pon_onu_mng);
         Config(script)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translated(vlan_profile: &str, id: u16) -> String {
+        let wan = Command::WanIp {
+            index: None,
+            mode: WanMode::Dhcp,
+            ip_profile: None,
+            vlan_profile: Some(vlan_profile.into()),
+            host: Some(1),
+        };
+        let (mut vlan, _) = Vlan::parse_wan(&wan).unwrap();
+        vlan.translate(id);
+        vlan.profile_name()
+    }
+
+    const C300_UNCFG: &str = "\
+OnuIndex                 Sn                  State
+---------------------------------------------------------------------
+gpon-onu_1/2/1:1         ZTEGC8D1A2B3        unknown
+gpon-onu_1/3/4:1         FHTT1234ABCD        unknown
+OLT-C300#";
+
+    const C600_UNCFG: &str = "\
+OltIndex                Model                SN                  PW
+----------------------- -------------------- ------------------- ----------
+gpon_olt-1/3/1          F670LV9.0            ZTEGD2B3C4D5        N/A
+gpon_olt-1/3/2          ZTE F601 V6.0        ZTEGC0D6D1E9        12345
+OLT-C600#";
+
+    #[test]
+    fn uncfg_c300() {
+        let onus = UncfgOnu::parse_table(C300_UNCFG);
+        assert_eq!(onus.len(), 2);
+        assert_eq!((onus[0].interface.slot, onus[0].interface.port), (2, 1));
+        assert_eq!(onus[0].interface.id, None);
+        assert_eq!(onus[0].sn, "ZTEGC8D1A2B3");
+        assert_eq!(onus[1].sn, "FHTT1234ABCD");
+        assert!(onus
+            .iter()
+            .all(|o| o.model.is_none() && o.password.is_none()));
+    }
+
+    #[test]
+    fn uncfg_c600() {
+        let onus = UncfgOnu::parse_table(C600_UNCFG);
+        assert_eq!(onus.len(), 2);
+        assert_eq!((onus[1].interface.slot, onus[1].interface.port), (3, 2));
+        assert_eq!(onus[0].sn, "ZTEGD2B3C4D5");
+        assert_eq!(onus[0].password, None);
+        assert_eq!(onus[0].type_guess().as_deref(), Some("ZTE-F670L"));
+        assert_eq!(onus[1].model.as_deref(), Some("ZTE F601 V6.0"));
+        assert_eq!(onus[1].password.as_deref(), Some("12345"));
+        assert_eq!(onus[1].type_guess().as_deref(), Some("ZTE-F601"));
+    }
+
+    #[test]
+    fn translate_vlan_profile() {
+        assert_eq!(translated("100", 200), "200");
+        assert_eq!(translated("VOIP200", 300), "VOIP300");
+        assert_eq!(translated("100_PPPOE", 110), "110_PPPOE");
+        assert_eq!(translated("vlan-100-100", 200), "vlan-200-100");
+    }
+}